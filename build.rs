@@ -1,6 +1,8 @@
+#[cfg(feature = "node")]
 extern crate neon_build;
 
 fn main() {
+    #[cfg(feature = "node")]
     neon_build::setup(); // must be called in build.rs
 
     // add project-specific build logic here...
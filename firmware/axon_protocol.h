@@ -0,0 +1,310 @@
+/* generated by `axon firmware header`, do not edit. */
+#ifndef AXON_PROTOCOL_H
+#define AXON_PROTOCOL_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+#define AXON_PROTOCOL_VERSION 3
+#define AXON_MIN_PROTOCOL_VERSION 3
+#define AXON_MAX_FRAME_SIZE 1024
+#define AXON_MIN_CUSTOM_ID 64
+#define AXON_BINARY_MARKER 0x02
+
+#ifndef AXON_CAPABILITIES
+#define AXON_CAPABILITIES "{\"versions\":[3],\"messageTypes\":[0,1,2,3,4,6],\"framing\":[\"envelope\"],\"maxFrameSize\":1024,\"codecs\":[\"json\"]}"
+#endif
+
+#define AXON_PREFIX_HANDSHAKE 'H'
+#define AXON_PREFIX_COMMAND 'C'
+#define AXON_PREFIX_QUERY 'Q'
+#define AXON_PREFIX_STATE 'S'
+#define AXON_PREFIX_TIME_SYNC 'T'
+
+#define AXON_TYPE_HANDSHAKE_REQUEST "handshakeRequest"
+#define AXON_TYPE_HANDSHAKE_RESPONSE "handshakeResponse"
+#define AXON_TYPE_RECORD "record"
+#define AXON_TYPE_STATE "state"
+#define AXON_TYPE_COMMAND "command"
+#define AXON_TYPE_QUERY "query"
+#define AXON_TYPE_QUERY_RESPONSE "queryResponse"
+#define AXON_TYPE_TIME_SYNC "timeSync"
+#define AXON_TYPE_TIME_SYNC_RESPONSE "timeSyncResponse"
+
+enum axon_handshake_type {
+  AXON_HANDSHAKE_CONNECT = 18499,
+  AXON_HANDSHAKE_ACCEPT = 18497,
+};
+
+enum axon_message_type {
+  AXON_RECORD_MESSAGE = 0,
+  AXON_STATE_MESSAGE = 1,
+  AXON_COMMAND_MESSAGE = 2,
+  AXON_QUERY_MESSAGE = 3,
+  AXON_CAPABILITY_MESSAGE = 4,
+  AXON_PAIR_MESSAGE = 5,
+  AXON_TIME_SYNC_MESSAGE = 6,
+};
+
+enum axon_record_type {
+  AXON_RECORD_SIMPLE = 83,
+  AXON_RECORD_MULTI = 78,
+};
+
+enum axon_command_code {
+  AXON_COMMAND_DIGITAL_WRITE = 68,
+  AXON_COMMAND_ANALOG_WRITE = 65,
+  AXON_COMMAND_PULSE = 80,
+  AXON_COMMAND_SERVO_ANGLE = 83,
+  AXON_COMMAND_READ_PIN = 82,
+  AXON_COMMAND_DISPENSE = 77,
+};
+
+enum axon_codec {
+  AXON_CODEC_MESSAGE_PACK = 2,
+  AXON_CODEC_CBOR = 1,
+  AXON_CODEC_JSON = 0,
+};
+
+#define AXON_MIN_PIN 2
+#define AXON_MAX_PIN 19
+#define AXON_MAX_SERVO_ANGLE 180
+#define AXON_MAX_PULSE_MILLIS 60000UL
+static const uint8_t AXON_PWM_PINS[6] = {3, 5, 6, 9, 10, 11};
+
+struct axon_frame {
+  char prefix;
+  const char *type;
+  size_t type_len;
+  const char *payload;
+  size_t payload_len;
+  uint32_t seq;
+  int has_seq;
+};
+
+/* returns a pointer just past the closing quote of the string at `p`. */
+static inline const char *axon_skip_string(const char *p, const char *end) {
+  for (p++; p < end; p++) {
+    if (*p == '\\') {
+      p++;
+    } else if (*p == '"') {
+      return p + 1;
+    }
+  }
+  return NULL;
+}
+
+static inline const char *axon_skip_space(const char *p, const char *end) {
+  while (p < end && (*p == ' ' || *p == '\t' || *p == '\r' || *p == '\n')) {
+    p++;
+  }
+  return p;
+}
+
+/* returns a pointer just past the JSON value starting at `p`. */
+static inline const char *axon_skip_value(const char *p, const char *end) {
+  int depth = 0;
+  if (p >= end) {
+    return NULL;
+  }
+  if (*p == '"') {
+    return axon_skip_string(p, end);
+  }
+  while (p < end) {
+    if (*p == '"') {
+      p = axon_skip_string(p, end);
+      if (p == NULL) {
+        return NULL;
+      }
+      continue;
+    }
+    if (*p == '{' || *p == '[') {
+      depth++;
+    } else if (*p == '}' || *p == ']') {
+      if (depth == 0) {
+        return p;
+      }
+      if (--depth == 0) {
+        return p + 1;
+      }
+    } else if (depth == 0 && (*p == ',' || *p == ' ')) {
+      return p;
+    }
+    p++;
+  }
+  return depth == 0 ? p : NULL;
+}
+
+/* finds `key` among the members of the object in `json` and points `value`
+   at its raw JSON. strings keep their quotes. returns 0 when found. */
+static inline int axon_member(const char *json, size_t len, const char *key,
+                              const char **value, size_t *value_len) {
+  const char *end = json + len;
+  const char *p = axon_skip_space(json, end);
+  size_t key_len = strlen(key);
+  if (p >= end || *p != '{') {
+    return -1;
+  }
+  p++;
+  while (p < end) {
+    const char *name;
+    const char *name_end;
+    p = axon_skip_space(p, end);
+    if (p >= end || *p != '"') {
+      return -1;
+    }
+    name = p + 1;
+    name_end = axon_skip_string(p, end);
+    if (name_end == NULL) {
+      return -1;
+    }
+    p = axon_skip_space(name_end, end);
+    if (p >= end || *p != ':') {
+      return -1;
+    }
+    p = axon_skip_space(p + 1, end);
+    if ((size_t)(name_end - name - 1) == key_len && memcmp(name, key, key_len) == 0) {
+      const char *value_end = axon_skip_value(p, end);
+      if (value_end == NULL) {
+        return -1;
+      }
+      *value = p;
+      *value_len = (size_t)(value_end - p);
+      return 0;
+    }
+    p = axon_skip_value(p, end);
+    if (p == NULL) {
+      return -1;
+    }
+    p = axon_skip_space(p, end);
+    if (p >= end || *p != ',') {
+      return -1;
+    }
+    p++;
+  }
+  return -1;
+}
+
+static inline int axon_member_u32(const char *json, size_t len, const char *key,
+                                  uint32_t *out) {
+  const char *value;
+  size_t value_len;
+  uint32_t number = 0;
+  size_t i;
+  if (axon_member(json, len, key, &value, &value_len) != 0 || value_len == 0) {
+    return -1;
+  }
+  for (i = 0; i < value_len; i++) {
+    if (value[i] < '0' || value[i] > '9') {
+      return -1;
+    }
+    number = number * 10 + (uint32_t)(value[i] - '0');
+  }
+  *out = number;
+  return 0;
+}
+
+/* points `out` at the characters of a string member, without quotes or
+   unescaping. */
+static inline int axon_member_string(const char *json, size_t len, const char *key,
+                                     const char **out, size_t *out_len) {
+  const char *value;
+  size_t value_len;
+  if (axon_member(json, len, key, &value, &value_len) != 0 || value_len < 2 ||
+      value[0] != '"') {
+    return -1;
+  }
+  *out = value + 1;
+  *out_len = value_len - 2;
+  return 0;
+}
+
+/* splits a line read from serial into its prefix and envelope. the frame
+   points into `line`, nothing is copied. returns 0 on success. */
+static inline int axon_parse_frame(const char *line, size_t len, struct axon_frame *frame) {
+  const char *meta;
+  size_t meta_len;
+  while (len > 0 && (line[len - 1] == '\n' || line[len - 1] == '\r')) {
+    len--;
+  }
+  frame->prefix = 0;
+  if (len > 0 && line[0] != '{') {
+    frame->prefix = line[0];
+    line++;
+    len--;
+  }
+  if (axon_member_string(line, len, "type", &frame->type, &frame->type_len) != 0) {
+    return -1;
+  }
+  if (axon_member(line, len, "payload", &frame->payload, &frame->payload_len) != 0) {
+    frame->payload = "null";
+    frame->payload_len = 4;
+  }
+  frame->seq = 0;
+  frame->has_seq = 0;
+  if (axon_member(line, len, "meta", &meta, &meta_len) == 0 &&
+      axon_member_u32(meta, meta_len, "seq", &frame->seq) == 0) {
+    frame->has_seq = 1;
+  }
+  return 0;
+}
+
+static inline int axon_frame_is(const struct axon_frame *frame, const char *type) {
+  return strlen(type) == frame->type_len && memcmp(frame->type, type, frame->type_len) == 0;
+}
+
+/* writes an envelope around `payload`, which must already be JSON. pass NULL
+   for `signature` on unsigned frames. returns the length written, or -1 when
+   the frame doesn't fit in `size` or AXON_MAX_FRAME_SIZE. the caller ends the
+   line, e.g. with Serial.println. */
+static inline int axon_build_frame(char *out, size_t size, const char *type,
+                                   const char *payload, uint32_t seq,
+                                   const char *signature) {
+  int written;
+  if (signature != NULL) {
+    written = snprintf(out, size,
+                       "{\"type\":\"%s\",\"payload\":%s,\"meta\":{\"seq\":%lu,\"signature\":\"%s\"}}",
+                       type, payload, (unsigned long)seq, signature);
+  } else {
+    written = snprintf(out, size, "{\"type\":\"%s\",\"payload\":%s,\"meta\":{\"seq\":%lu}}",
+                       type, payload, (unsigned long)seq);
+  }
+  if (written < 0 || (size_t)written >= size || written > AXON_MAX_FRAME_SIZE) {
+    return -1;
+  }
+  return written;
+}
+
+/* the connect a device sends before a record or any other device initiated
+   message. */
+static inline int axon_build_connect(char *out, size_t size, int message_type,
+                                     const char *device_id, uint32_t seq) {
+  char payload[384];
+  int written = snprintf(payload, sizeof(payload),
+                         "{\"handshakeType\":%d,\"messageType\":%d,\"version\":%d,\"capabilities\":%s,\"deviceId\":\"%s\"}",
+                         AXON_HANDSHAKE_CONNECT, message_type, AXON_PROTOCOL_VERSION,
+                         AXON_CAPABILITIES, device_id);
+  if (written < 0 || (size_t)written >= sizeof(payload)) {
+    return -1;
+  }
+  return axon_build_frame(out, size, AXON_TYPE_HANDSHAKE_REQUEST, payload, seq, NULL);
+}
+
+/* answers a host connect. `signature` is the device's signature over the
+   host's challenge. */
+static inline int axon_build_accept(char *out, size_t size, const char *device_id,
+                                    const char *signature, uint32_t seq) {
+  char payload[512];
+  int written = snprintf(payload, sizeof(payload),
+                         "{\"handshakeType\":%d,\"version\":%d,\"capabilities\":%s,\"deviceId\":\"%s\",\"signature\":\"%s\"}",
+                         AXON_HANDSHAKE_ACCEPT, AXON_PROTOCOL_VERSION, AXON_CAPABILITIES,
+                         device_id, signature);
+  if (written < 0 || (size_t)written >= sizeof(payload)) {
+    return -1;
+  }
+  return axon_build_frame(out, size, AXON_TYPE_HANDSHAKE_RESPONSE, payload, seq, NULL);
+}
+
+#endif /* AXON_PROTOCOL_H */
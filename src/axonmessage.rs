@@ -1,28 +1,223 @@
 pub mod axonmessage {
-    use serde::{Deserialize, Serialize};
+    use crate::codec::codec::{Codec, BINARY_MARKER};
+    use crate::registry::message_registry::MessageRegistry;
     use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Serialize};
     use serde_json::Error as SerdeError;
+    use serde_json::Value;
+    use std::io::{Error, ErrorKind};
+
+    pub const BUILTIN_MESSAGE_TYPES: [&str; 9] = [
+        "handshakeRequest",
+        "handshakeResponse",
+        "record",
+        "state",
+        "command",
+        "query",
+        "queryResponse",
+        "timeSync",
+        "timeSyncResponse",
+    ];
 
     // marker trait for sendable objects.
     pub trait Sendable {}
     pub trait Recievable {}
 
     pub trait AxonMessage: Serialize + DeserializeOwned {
+        // the `type` discriminator this message travels under.
+        fn message_type() -> &'static str;
+
         fn to_json_string(&self) -> Result<String, SerdeError>
         where
             Self: Serialize,
         {
             serde_json::to_string(&self)
         }
+
+        fn encode(&self, codec: Codec) -> Result<Vec<u8>, Error> {
+            codec.encode(self)
+        }
+
+        fn decode(bytes: &[u8], codec: Codec) -> Result<Self, Error> {
+            codec.decode(bytes)
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(untagged)]
+    #[derive(Debug)]
     pub enum Message<T> {
         AxonMessage(T),
         Empty,
         Log { status: i8, data: String },
     }
 
-    impl<T: Serialize + DeserializeOwned> AxonMessage for Message<T> {}
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+    pub struct Metadata {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub seq: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub signature: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct Envelope {
+        #[serde(rename = "type")]
+        pub message_type: String,
+        #[serde(default)]
+        pub payload: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub meta: Option<Metadata>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum Frame {
+        Envelope(Envelope),
+        Log(String),
+    }
+
+    impl Envelope {
+        pub fn new(message_type: &str, payload: Value) -> Envelope {
+            Envelope {
+                message_type: message_type.to_string(),
+                payload,
+                meta: None,
+            }
+        }
+
+        pub fn wrap<T: AxonMessage>(
+            message: &T,
+            meta: Option<Metadata>,
+        ) -> Result<Envelope, Error> {
+            Ok(Envelope {
+                message_type: T::message_type().to_string(),
+                payload: serde_json::to_value(message)?,
+                meta,
+            })
+        }
+
+        // anything that isn't a JSON object is the device printing to serial and
+        // is passed on as a log line.
+        pub fn decode(data: &str) -> Result<Frame, Error> {
+            match serde_json::from_str::<Value>(data) {
+                Ok(value @ Value::Object(_)) => Self::checked(value),
+                _ => Ok(Frame::Log(data.to_string())),
+            }
+        }
+
+        // a frame as read off the wire, either a text line or a binary frame
+        // from `Codec::frame`.
+        pub fn decode_bytes(bytes: &[u8]) -> Result<Frame, Error> {
+            let (id, length, body) = match bytes {
+                [BINARY_MARKER, id, high, low, body @ ..] => {
+                    (*id, u16::from_be_bytes([*high, *low]), body)
+                }
+                _ => {
+                    let line = String::from_utf8_lossy(bytes);
+                    return Self::decode(line.trim_end_matches(&['\r', '\n'][..]));
+                }
+            };
+            let codec = Codec::from_id(id).ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, format!("unknown codec id {}", id))
+            })?;
+            if usize::from(length) != body.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "binary frame says {} bytes but carries {}",
+                        length,
+                        body.len()
+                    ),
+                ));
+            }
+            match codec.decode::<Value>(body)? {
+                value @ Value::Object(_) => Self::checked(value),
+                value => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("binary frame is not an envelope: {}", value),
+                )),
+            }
+        }
+
+        // objects have to carry a known `type`.
+        fn checked(value: Value) -> Result<Frame, Error> {
+            let message_type = match value.get("type").and_then(Value::as_str) {
+                Some(message_type) => message_type.to_string(),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("frame has no type discriminator: {}", value),
+                    ))
+                }
+            };
+            if !BUILTIN_MESSAGE_TYPES.contains(&message_type.as_str())
+                && !MessageRegistry::is_registered(&message_type)
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown message type `{}`", message_type),
+                ));
+            }
+            Ok(Frame::Envelope(serde_json::from_value(value)?))
+        }
+
+        pub fn is<T: AxonMessage>(&self) -> bool {
+            self.message_type == T::message_type()
+        }
+
+        pub fn open<T: AxonMessage>(self) -> Result<T, Error> {
+            if !self.is::<T>() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "expected a `{}` frame, got `{}`",
+                        T::message_type(),
+                        self.message_type
+                    ),
+                ));
+            }
+            Ok(serde_json::from_value(self.payload)?)
+        }
+
+        pub fn seq(&self) -> Option<u32> {
+            self.meta.as_ref().and_then(|meta| meta.seq)
+        }
+
+        pub fn signature(&self) -> Option<&str> {
+            self.meta
+                .as_ref()
+                .and_then(|meta| meta.signature.as_deref())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axonmessage::axonmessage::{Envelope, Frame};
+    use crate::state::device_state::State;
+
+    #[test]
+    fn decode_frames() {
+        let state = "{\"type\":\"state\",\"payload\":{\"ownerPublicKey\":\"A\",\"nodeIp\":\"B\",\"genHash\":\"C\"},\"meta\":{\"seq\":4}}";
+        match Envelope::decode(state).unwrap() {
+            Frame::Envelope(envelope) => {
+                assert_eq!(envelope.seq(), Some(4));
+                assert_eq!(envelope.open::<State>().unwrap().node_ip, "B");
+            }
+            Frame::Log(_) => panic!("state frame decoded as a log line"),
+        }
+        assert_eq!(
+            Envelope::decode("booting sensor").unwrap(),
+            Frame::Log(String::from("booting sensor"))
+        );
+        assert!(Envelope::decode("{\"status\":0,\"data\":\"x\"}").is_err());
+        assert!(Envelope::decode("{\"type\":\"firmware\",\"payload\":{}}").is_err());
+    }
+
+    #[test]
+    fn open_checks_type() {
+        let record = "{\"type\":\"record\",\"payload\":{}}";
+        match Envelope::decode(record).unwrap() {
+            Frame::Envelope(envelope) => assert!(envelope.open::<State>().is_err()),
+            Frame::Log(_) => panic!("record frame decoded as a log line"),
+        }
+    }
 }
@@ -0,0 +1,422 @@
+extern crate axon;
+extern crate serde;
+extern crate serde_json;
+extern crate serialport;
+
+use axon::command::command::{Command, Operation};
+use axon::export::record_export::{Column, ExportFormat, RecordExport};
+use axon::firmware::firmware_header::FirmwareHeader;
+use axon::history::record_history::{
+    RecordHistory, RecordQuery, RecordStore, DEFAULT_MAX_AGE_DAYS, DEFAULT_MAX_BYTES,
+};
+use axon::idenity::device_identity::Identity;
+use axon::init::init::AxonInit;
+use axon::record::record::Record;
+use axon::rules::rule_engine::RuleEngine;
+use axon::schedule::command_schedule::{Schedule, Scheduler, Schedules};
+use axon::schema::message_schema::Schemas;
+use axon::serial::serial_handler::{SerialData, DEFAULT_SETTINGS};
+use axon::state::device_state::State;
+use serde::Serialize;
+use serde_json::{json, Value};
+use serialport::SerialPortType;
+use std::env;
+use std::io::Result as SingleResult;
+use std::io::{Error, ErrorKind};
+use std::process;
+
+const USAGE: &str = "usage: axon [--json] <command>
+
+commands:
+  init                                    create the /axon data directory
+  identity show                           print the device identity
+  identity new [--force]                  generate a new identity
+  state get                               print the saved state
+  state set <owner-key> <node> <gen-hash> save a new state
+  ports list                              list serial ports
+  watch records <port>                    print records as the device sends them
+  watch state <port>                      save state as the device sends it
+  send command <port> <operation-json>    send a command, e.g. '{\"op\":\"readPin\",\"pin\":13}'
+  raw read <port>                         read one line from the port
+  raw write <port> <data>                 write data to the port as is
+  firmware header [<path>]                generate the C header for Arduino sketches
+  schemas [<dir>]                         print the message schemas, or write them to <dir>
+  export csv|ndjson <path> [options]      export stored records to a file
+  rules check <path>                      validate a JSON or TOML rules file
+  schedule list                           list scheduled commands and their last run
+  schedule add|update <schedule-json>     e.g. '{\"name\":\"pump\",\"path\":\"/dev/ttyACM0\",
+                                          \"trigger\":{\"kind\":\"cron\",\"expression\":\"0 6 * * *\"},
+                                          \"operation\":{\"op\":\"pulse\",\"pin\":8,\"millis\":5000}}'
+  schedule remove <name>                  delete a schedule
+  schedule run                            run due schedules until interrupted
+
+export options:
+  --device <id>  --sensor <name>  --from <ms>  --to <ms>
+  --columns <a,b,...>                     CSV columns, defaults to receivedAt,deviceId,sensor,value,unit,quality
+  --store <dir>                           the record store, defaults to /axon/records";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    Json,
+    Human,
+}
+
+fn usage_error() -> Error {
+    Error::new(ErrorKind::InvalidInput, USAGE)
+}
+
+// prints `value` as a single JSON line, or `human` for people at a terminal.
+fn print<T: Serialize>(output: Output, value: &T, human: String) -> SingleResult<()> {
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(value)?),
+        Output::Human => println!("{}", human),
+    }
+    Ok(())
+}
+
+fn port_list(output: Output) -> SingleResult<()> {
+    let ports = serialport::available_ports()?;
+    let ports: Vec<Value> = ports
+        .into_iter()
+        .map(|info| match info.port_type {
+            SerialPortType::UsbPort(usb) => json!({
+                "name": info.port_name,
+                "type": "usb",
+                "vid": usb.vid,
+                "pid": usb.pid,
+                "serialNumber": usb.serial_number,
+                "manufacturer": usb.manufacturer,
+                "product": usb.product,
+            }),
+            SerialPortType::PciPort => json!({ "name": info.port_name, "type": "pci" }),
+            SerialPortType::BluetoothPort => {
+                json!({ "name": info.port_name, "type": "bluetooth" })
+            }
+            SerialPortType::Unknown => json!({ "name": info.port_name, "type": "unknown" }),
+        })
+        .collect();
+    let human = ports
+        .iter()
+        .map(|port| {
+            let serial = port["serialNumber"].as_str().unwrap_or("-");
+            format!(
+                "{}\t{}\t{}",
+                port["name"].as_str().unwrap_or_default(),
+                port["type"].as_str().unwrap_or_default(),
+                serial
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    print(output, &ports, human)
+}
+
+fn identity(output: Output, args: &[&str]) -> SingleResult<()> {
+    let path = String::from(axon::IDENTITY_PATH);
+    match args {
+        ["show"] => {}
+        ["new"] if Identity::check_identity(&path)? => {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "an identity already exists, pass --force to replace it",
+            ))
+        }
+        ["new"] => Identity::create_identity(&path)?,
+        ["new", "--force"] => Identity::replace_identity(&path)?,
+        _ => return Err(usage_error()),
+    }
+    let identity: Value = serde_json::from_str(&Identity::load_identity_from_path(&path)?)?;
+    let human = format!(
+        "identifier: {}\nkey: {}",
+        identity["identifer"].as_str().unwrap_or_default(),
+        identity["key"].as_str().unwrap_or_default()
+    );
+    print(output, &identity, human)
+}
+
+fn state(output: Output, args: &[&str]) -> SingleResult<()> {
+    let path = String::from(axon::STATE_PATH);
+    let state = match args {
+        ["get"] => serde_json::from_str::<State>(&State::load_state(&path)?)?,
+        ["set", owner_public_key, node_ip, gen_hash] => {
+            let state = State {
+                owner_public_key: owner_public_key.to_string(),
+                node_ip: node_ip.to_string(),
+                gen_hash: gen_hash.to_string(),
+            };
+            state.validate()?;
+            State::save_state(
+                state.owner_public_key.clone(),
+                state.node_ip.clone(),
+                state.gen_hash.clone(),
+                &path,
+            )?;
+            state
+        }
+        _ => return Err(usage_error()),
+    };
+    let human = format!(
+        "owner public key: {}\nnode: {}\ngeneration hash: {}",
+        state.owner_public_key, state.node_ip, state.gen_hash
+    );
+    print(output, &state, human)
+}
+
+// runs until the port goes away. read timeouts just mean the device had
+// nothing to say yet.
+fn watch(output: Output, args: &[&str]) -> SingleResult<()> {
+    let state_path = String::from(axon::STATE_PATH);
+    match args {
+        ["records", path] => {
+            let mut port = SerialData::open_port(DEFAULT_SETTINGS, &path.to_string())?;
+            // a live read path like any other, so the records go to history too.
+            RecordHistory::open_default();
+            loop {
+                match Record::watch_port(&mut port) {
+                    Ok(record) => {
+                        RecordHistory::store(&record);
+                        print(output, &record, format!("{:?}", record))?;
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::TimedOut => (),
+                    Err(ref e) if e.kind() == ErrorKind::InvalidData => eprintln!("axon: {}", e),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        ["state", path] => {
+            let mut port = SerialData::open_port(DEFAULT_SETTINGS, &path.to_string())?;
+            loop {
+                if State::watch_state_port(&state_path, &mut port)? {
+                    let state: State = serde_json::from_str(&State::load_state(&state_path)?)?;
+                    print(output, &state, format!("saved state from {}", path))?;
+                }
+            }
+        }
+        _ => Err(usage_error()),
+    }
+}
+
+fn send(output: Output, args: &[&str]) -> SingleResult<()> {
+    let (path, operation) = match args {
+        ["command", path, operation] => (path.to_string(), operation),
+        _ => return Err(usage_error()),
+    };
+    let operation: Operation = serde_json::from_str(operation)?;
+    let response = Command::send_command(path, DEFAULT_SETTINGS, operation)?;
+    let human = format!(
+        "{} on pin {}: {}",
+        response.operation.name(),
+        response.pin,
+        if response.status { "ok" } else { "failed" }
+    );
+    print(output, &response, human)
+}
+
+fn raw(output: Output, args: &[&str]) -> SingleResult<()> {
+    match args {
+        ["read", path] => {
+            let mut port = SerialData::open_port(DEFAULT_SETTINGS, &path.to_string())?;
+            let data = SerialData::read_port(&mut port)?;
+            print(output, &json!({ "data": data }), data.clone())
+        }
+        ["write", path, data] => {
+            let mut port = SerialData::open_port(DEFAULT_SETTINGS, &path.to_string())?;
+            let written = SerialData::write_port(data.to_string(), &mut port)?;
+            print(
+                output,
+                &json!({ "written": written }),
+                format!("wrote {} bytes", data.len()),
+            )
+        }
+        _ => Err(usage_error()),
+    }
+}
+
+fn firmware(output: Output, args: &[&str]) -> SingleResult<()> {
+    match args {
+        ["header"] => {
+            print!("{}", FirmwareHeader::render());
+            Ok(())
+        }
+        ["header", path] => {
+            FirmwareHeader::write(path)?;
+            print(output, &json!({ "path": path }), format!("wrote {}", path))
+        }
+        _ => Err(usage_error()),
+    }
+}
+
+fn schemas(output: Output, args: &[&str]) -> SingleResult<()> {
+    match args {
+        [] => {
+            println!("{}", serde_json::to_string_pretty(Schemas::all())?);
+            Ok(())
+        }
+        [dir] => {
+            let written = Schemas::write(dir)?;
+            let human = written.join("\n");
+            print(output, &written, human)
+        }
+        _ => Err(usage_error()),
+    }
+}
+
+fn rules(output: Output, args: &[&str]) -> SingleResult<()> {
+    let path = match args {
+        ["check", path] => path,
+        _ => return Err(usage_error()),
+    };
+    let rules = RuleEngine::parse_file(path)?;
+    let human = rules
+        .iter()
+        .map(|rule| format!("{}\t{}\t{}", rule.name, rule.sensor, rule.operation.name()))
+        .collect::<Vec<String>>()
+        .join("\n");
+    print(output, &rules, human)
+}
+
+fn schedule(output: Output, args: &[&str]) -> SingleResult<()> {
+    let path = String::from(axon::SCHEDULES_PATH);
+    match args {
+        ["list"] => {
+            let schedules = Schedules::list(&path)?;
+            let human = schedules
+                .iter()
+                .map(|schedule| {
+                    let last = match (schedule.last_status, &schedule.last_error) {
+                        (_, Some(error)) => error.clone(),
+                        (Some(true), _) => String::from("ok"),
+                        (Some(false), _) => String::from("refused"),
+                        (None, _) => String::from("never run"),
+                    };
+                    format!(
+                        "{}\t{}\t{}\t{}",
+                        schedule.name,
+                        if schedule.enabled { "on" } else { "off" },
+                        schedule.operation.name(),
+                        last
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            print(output, &schedules, human)
+        }
+        ["add", schedule] | ["update", schedule] => {
+            let schedule: Schedule = serde_json::from_str(schedule)?;
+            let name = schedule.name.clone();
+            if args[0] == "add" {
+                Schedules::add(&path, schedule)?;
+            } else {
+                Schedules::update(&path, schedule)?;
+            }
+            print(output, &json!({ "name": name }), format!("saved {}", name))
+        }
+        ["remove", name] => {
+            let removed = Schedules::remove(&path, name)?;
+            let human = if removed {
+                format!("removed {}", name)
+            } else {
+                format!("no schedule named {}", name)
+            };
+            print(output, &json!({ "removed": removed }), human)
+        }
+        ["run"] => {
+            Scheduler::run(&path);
+            Ok(())
+        }
+        _ => Err(usage_error()),
+    }
+}
+
+fn millis(value: &str) -> SingleResult<u64> {
+    value.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("`{}` is not a time in milliseconds", value),
+        )
+    })
+}
+
+fn export(output: Output, args: &[&str]) -> SingleResult<()> {
+    let (format, path, mut options) = match args {
+        [format, path, options @ ..] => (*format, *path, options),
+        _ => return Err(usage_error()),
+    };
+    let mut query = RecordQuery::default();
+    let mut columns = Column::DEFAULT.to_vec();
+    let mut store = String::from(axon::RECORDS_PATH);
+    while let [option, value, rest @ ..] = options {
+        match *option {
+            "--device" => query.device_id = Some(value.to_string()),
+            "--sensor" => query.sensor = Some(value.to_string()),
+            "--from" => query.from = Some(millis(value)?),
+            "--to" => query.to = Some(millis(value)?),
+            "--columns" => columns = Column::parse_list(value)?,
+            "--store" => store = value.to_string(),
+            _ => return Err(usage_error()),
+        }
+        options = rest;
+    }
+    if !options.is_empty() {
+        return Err(usage_error());
+    }
+    let format = match format {
+        "csv" => ExportFormat::Csv(columns),
+        "ndjson" => ExportFormat::Ndjson,
+        _ => return Err(usage_error()),
+    };
+    let store = RecordStore::new(store, DEFAULT_MAX_AGE_DAYS, DEFAULT_MAX_BYTES);
+    let rows = RecordExport::to_file(&store, &query, &format, path)?;
+    print(
+        output,
+        &json!({ "path": path, "rows": rows }),
+        format!("wrote {} rows to {}", rows, path),
+    )
+}
+
+fn run(output: Output, args: &[&str]) -> SingleResult<()> {
+    match args {
+        ["init"] => {
+            AxonInit::init_fs()?;
+            print(
+                output,
+                &json!({ "path": axon::PARENT_PATH }),
+                format!("initialized {}", axon::PARENT_PATH),
+            )
+        }
+        ["identity", rest @ ..] => identity(output, rest),
+        ["state", rest @ ..] => state(output, rest),
+        ["ports", "list"] => port_list(output),
+        ["watch", rest @ ..] => watch(output, rest),
+        ["send", rest @ ..] => send(output, rest),
+        ["raw", rest @ ..] => raw(output, rest),
+        ["firmware", rest @ ..] => firmware(output, rest),
+        ["schemas", rest @ ..] => schemas(output, rest),
+        ["export", rest @ ..] => export(output, rest),
+        ["rules", rest @ ..] => rules(output, rest),
+        ["schedule", rest @ ..] => schedule(output, rest),
+        ["help"] | ["--help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(usage_error()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let output = if args.iter().any(|arg| arg == "--json") {
+        Output::Json
+    } else {
+        Output::Human
+    };
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json")
+        .collect();
+    if let Err(e) = run(output, &args) {
+        eprintln!("axon: {}", e);
+        process::exit(1);
+    }
+}
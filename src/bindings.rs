@@ -1,25 +1,44 @@
 pub mod bindings {
 
-    use crate::axonmessage::axonmessage::AxonMessage;
-    use crate::command::command::Command;
+    use crate::axonmessage::axonmessage::{AxonMessage, Message};
+    use crate::capture::capture::{Capture, ReplayPort};
+    use crate::command::command::{Command, Mosaic, Operation};
+    use crate::devicelog::device_log::{DeviceLog, DeviceLogs, RotatingFile};
+    use crate::export::record_export::{Column, ExportFormat, RecordExport};
+    use crate::handshake::handshake::{AxonMessageStatus, Handshake};
+    use crate::history::record_history::{
+        RecordHistory, RecordQuery, RecordStore, DEFAULT_MAX_AGE_DAYS, DEFAULT_MAX_BYTES,
+    };
     use crate::idenity::device_identity::Identity;
     use crate::init::init::AxonInit;
+    use crate::logging::host_log::{HostLog, LogRecord};
+    use crate::metrics::link_metrics::Metrics;
+    use crate::pairing::device_pairing::{PairedDevice, PairedDevices};
+    use crate::query::query::{Query, QueryValue};
     use crate::record::record::Record;
-    use crate::serial::serial_handler::SerialData;
+    use crate::registry::message_registry::MessageRegistry;
+    use crate::rules::rule_engine::{RuleEngine, Rules};
+    use crate::schedule::command_schedule::{Schedule, Scheduler, Schedules};
+    use crate::schema::message_schema::Schemas;
+    use crate::sequence::sequence::SequenceTracker;
+    use crate::serial::serial_handler::{SerialData, DEFAULT_SETTINGS};
     use crate::state::device_state::State;
+    use crate::supervisor::device_supervisor::{DeviceEvent, DeviceSupervisor};
+    use crate::timesync::time_sync::TimeSync;
     use neon::prelude::*;
     use serialport::prelude::*;
     use std::borrow::BorrowMut;
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
-    const SETTINGS: SerialPortSettings = SerialPortSettings {
-        baud_rate: 9600,
-        data_bits: DataBits::Eight,
-        flow_control: FlowControl::None,
-        parity: Parity::None,
-        stop_bits: StopBits::One,
-        timeout: Duration::from_millis(20000),
-    };
+    const SETTINGS: SerialPortSettings = DEFAULT_SETTINGS;
+
+    const SUPERVISOR_POLL: Duration = Duration::from_millis(1000);
+    const EVENT_POLL: Duration = Duration::from_millis(100);
+    const CLOCK_SYNC_TASK: &str = "clockSync";
+    const LOG_FILE_MAX_BYTES: u64 = 1024 * 1024;
+    const LOG_FILE_COUNT: u32 = 5;
 
     pub fn serial_read(mut cx: FunctionContext) -> JsResult<JsString> {
         let path = cx.argument::<JsString>(0)?.value();
@@ -53,20 +72,39 @@ pub mod bindings {
         Ok(status_obj)
     }
 
-    pub fn send_command(mut cx: FunctionContext) -> JsResult<JsObject> {
-        let path = cx.argument::<JsString>(0)?.value();
-        let command = cx.argument::<JsNumber>(1)?.value() as i8;
-        let pin = cx.argument::<JsNumber>(2)?.value() as i8;
-        let operation = cx.argument::<JsString>(3)?.value();
-        let amount = cx.argument::<JsNumber>(4)?.value() as i8;
+    // largest integer a JS number holds without losing precision.
+    const MAX_SAFE_INTEGER: u64 = 9_007_199_254_740_991;
+
+    fn integer_argument(cx: &mut FunctionContext, i: i32, max: u64) -> NeonResult<u64> {
+        let value = cx.argument::<JsNumber>(i)?.value();
+        if value.fract() != 0.0 || value < 0.0 || value > max as f64 {
+            return cx.throw_range_error(format!(
+                "argument {} must be an integer between 0 and {}",
+                i, max
+            ));
+        }
+        Ok(value as u64)
+    }
 
+    // optional, false when left out.
+    fn boolean_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<bool> {
+        match cx.argument_opt(i) {
+            Some(value) if value.is_a::<JsBoolean>() => {
+                Ok(value.downcast::<JsBoolean>().or_throw(cx)?.value())
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn run_operation(mut cx: FunctionContext, operation: Operation) -> JsResult<JsObject> {
+        let path = cx.argument::<JsString>(0)?.value();
         let status_obj = JsObject::new(&mut cx);
-        let response = Command::send_command(path, SETTINGS, command, pin, amount, operation)
+        let response = Command::send_command(path, SETTINGS, operation)
             .or_else(|e| cx.throw_error(e.to_string()))?;
 
         let write_status = cx.boolean(response.status);
         let pin = cx.number(response.pin);
-        let response = cx.string(response.operation);
+        let response = cx.string(response.operation.name());
 
         status_obj.set(&mut cx, "status", write_status)?;
         status_obj.set(&mut cx, "pinNo", pin)?;
@@ -74,6 +112,80 @@ pub mod bindings {
         Ok(status_obj)
     }
 
+    pub fn send_command(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let operation = cx.argument::<JsString>(1)?.value();
+        let operation: Operation =
+            serde_json::from_str(&operation).or_else(|e| cx.throw_type_error(e.to_string()))?;
+        run_operation(cx, operation)
+    }
+
+    pub fn digital_write(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let pin = integer_argument(&mut cx, 1, 255)? as u8;
+        let value = cx.argument::<JsBoolean>(2)?.value();
+        run_operation(cx, Operation::DigitalWrite { pin, value })
+    }
+
+    pub fn analog_write(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let pin = integer_argument(&mut cx, 1, 255)? as u8;
+        let value = integer_argument(&mut cx, 2, 255)? as u8;
+        run_operation(cx, Operation::AnalogWrite { pin, value })
+    }
+
+    pub fn pulse(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let pin = integer_argument(&mut cx, 1, 255)? as u8;
+        let millis = integer_argument(&mut cx, 2, u64::from(u32::MAX))? as u32;
+        run_operation(cx, Operation::Pulse { pin, millis })
+    }
+
+    pub fn servo_write(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let pin = integer_argument(&mut cx, 1, 255)? as u8;
+        let angle = integer_argument(&mut cx, 2, 255)? as u8;
+        run_operation(cx, Operation::ServoAngle { pin, angle })
+    }
+
+    fn run_query(mut cx: FunctionContext, operation: Operation) -> JsResult<JsValue> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let value =
+            Query::query(path, SETTINGS, operation).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(match value {
+            QueryValue::Number(number) => cx.number(number).upcast(),
+            QueryValue::Text(text) => cx.string(text).upcast(),
+        })
+    }
+
+    pub fn query(mut cx: FunctionContext) -> JsResult<JsValue> {
+        let operation = cx.argument::<JsString>(1)?.value();
+        let operation: Operation =
+            serde_json::from_str(&operation).or_else(|e| cx.throw_type_error(e.to_string()))?;
+        run_query(cx, operation)
+    }
+
+    pub fn read_pin(mut cx: FunctionContext) -> JsResult<JsValue> {
+        let pin = integer_argument(&mut cx, 1, 255)? as u8;
+        run_query(cx, Operation::ReadPin { pin })
+    }
+
+    pub fn dispense(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let pin = integer_argument(&mut cx, 1, 255)? as u8;
+        let amount = integer_argument(&mut cx, 2, MAX_SAFE_INTEGER)?;
+        let mosaic = match cx.argument_opt(3) {
+            Some(id) if id.is_a::<JsString>() => {
+                let id = id.downcast::<JsString>().or_throw(&mut cx)?.value();
+                let divisibility = integer_argument(&mut cx, 4, 255)? as u8;
+                Some(Mosaic { id, divisibility })
+            }
+            _ => None,
+        };
+        run_operation(
+            cx,
+            Operation::Dispense {
+                pin,
+                amount,
+                mosaic,
+            },
+        )
+    }
+
     pub fn load_identity(mut cx: FunctionContext) -> JsResult<JsString> {
         let path = crate::IDENTITY_PATH.to_string();
         let identity =
@@ -103,18 +215,752 @@ pub mod bindings {
         Ok(cx.boolean(status))
     }
 
+    pub fn provision_device(mut cx: FunctionContext) -> JsResult<JsString> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let state = cx.argument::<JsString>(1)?.value();
+        let state: State =
+            serde_json::from_str(&state).or_else(|e| cx.throw_type_error(e.to_string()))?;
+        let stored =
+            State::provision(&state, &path, SETTINGS).or_else(|e| cx.throw_error(e.to_string()))?;
+        let stored = stored
+            .to_json_string()
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(stored))
+    }
+
     pub fn watch_record(mut cx: FunctionContext) -> JsResult<JsString> {
         let path = cx.argument::<JsString>(0)?.value();
-        let record = Record::watch(&path, SETTINGS).or_else(|e| cx.throw_error(e.to_string()))?;
-        println!("{:?}", record);
+        let mut port =
+            SerialData::open_port(SETTINGS, &path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let mut record =
+            Record::watch_port(&mut port).or_else(|e| cx.throw_error(e.to_string()))?;
+        RecordHistory::store(&record);
+        // sent before returning, the port is exclusive and the next watchRecord
+        // has to be able to open it again.
+        Rules::apply(&mut port, &record);
+        // the readings travel with the record, there is no second reader to race.
+        record
+            .fill_readings()
+            .or_else(|e| cx.throw_error(e.to_string()))?;
         let record_serialized = record
             .to_json_string()
             .or_else(|e| cx.throw_error(e.to_string()))?;
         Ok(cx.string(record_serialized))
     }
 
+    // one round trip now, as JSON. see `TimeSync::sync_port`.
+    pub fn sync_clock(mut cx: FunctionContext) -> JsResult<JsString> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let clock = TimeSync::sync(path, SETTINGS).or_else(|e| cx.throw_error(e.to_string()))?;
+        let clock = serde_json::to_string(&clock).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(clock))
+    }
+
+    pub fn start_capture(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let path = cx.argument::<JsString>(0)?.value();
+        Capture::start(&path).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(true))
+    }
+
+    pub fn stop_capture(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        Ok(cx.boolean(Capture::stop()))
+    }
+
+    pub fn replay_record(mut cx: FunctionContext) -> JsResult<JsString> {
+        let capture_path = cx.argument::<JsString>(0)?.value();
+        let mut port =
+            ReplayPort::open(&capture_path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let record = Record::watch_port(&mut port).or_else(|e| cx.throw_error(e.to_string()))?;
+        let record = record
+            .to_json_string()
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(record))
+    }
+
+    // the replayed state is written to `statePath`, never to the live state file.
+    pub fn replay_state(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let capture_path = cx.argument::<JsString>(0)?.value();
+        let state_path = cx.argument::<JsString>(1)?.value();
+        let mut port =
+            ReplayPort::open(&capture_path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let status = State::watch_state_port(&state_path, &mut port)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(status))
+    }
+
+    pub fn sequence_stats(mut cx: FunctionContext) -> JsResult<JsObject> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let stats = SequenceTracker::stats(&path);
+        let stats_obj = JsObject::new(&mut cx);
+        let sent = cx.number(stats.sent as f64);
+        let received = cx.number(stats.received as f64);
+        let duplicates = cx.number(stats.duplicates as f64);
+        let gaps = cx.number(stats.gaps as f64);
+        let missing = cx.number(stats.missing as f64);
+
+        stats_obj.set(&mut cx, "sent", sent)?;
+        stats_obj.set(&mut cx, "received", received)?;
+        stats_obj.set(&mut cx, "duplicates", duplicates)?;
+        stats_obj.set(&mut cx, "gaps", gaps)?;
+        stats_obj.set(&mut cx, "missing", missing)?;
+        Ok(stats_obj)
+    }
+
+    // per-port link metrics as JSON, or in the Prometheus text format when
+    // called with "prometheus".
+    pub fn get_stats(mut cx: FunctionContext) -> JsResult<JsString> {
+        let format = match cx.argument_opt(0) {
+            Some(format) if format.is_a::<JsString>() => {
+                format.downcast::<JsString>().or_throw(&mut cx)?.value()
+            }
+            _ => String::from("json"),
+        };
+        let stats = match format.as_str() {
+            "json" => {
+                serde_json::to_string(&Metrics::all()).or_else(|e| cx.throw_error(e.to_string()))?
+            }
+            "prometheus" => Metrics::prometheus(),
+            _ => return cx.throw_type_error(format!("unknown stats format `{}`", format)),
+        };
+        Ok(cx.string(stats))
+    }
+
+    pub fn negotiate(mut cx: FunctionContext) -> JsResult<JsString> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let mut port =
+            SerialData::open_port(SETTINGS, &path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let negotiation =
+            Handshake::negotiate(port.borrow_mut()).or_else(|e| cx.throw_error(e.to_string()))?;
+        let negotiation =
+            serde_json::to_string(&negotiation).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(negotiation))
+    }
+
+    pub fn pair_device(mut cx: FunctionContext) -> JsResult<JsString> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let mut port =
+            SerialData::open_port(SETTINGS, &path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let force = boolean_argument(&mut cx, 1)?;
+        let device =
+            Handshake::pair(port.borrow_mut(), force).or_else(|e| cx.throw_error(e.to_string()))?;
+        let device = serde_json::to_string(&device).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(device))
+    }
+
+    pub fn add_paired_device(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let device_id = cx.argument::<JsString>(0)?.value();
+        let public_key = cx.argument::<JsString>(1)?.value();
+        let force = boolean_argument(&mut cx, 2)?;
+        PairedDevices::pair(
+            &String::from(crate::PAIRED_DEVICES_PATH),
+            PairedDevice {
+                device_id,
+                public_key,
+            },
+            force,
+        )
+        .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(true))
+    }
+
+    pub fn unpair_device(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let device_id = cx.argument::<JsString>(0)?.value();
+        let removed = PairedDevices::unpair(&String::from(crate::PAIRED_DEVICES_PATH), &device_id)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(removed))
+    }
+
+    pub fn list_paired_devices(mut cx: FunctionContext) -> JsResult<JsString> {
+        let paired = PairedDevices::load(&String::from(crate::PAIRED_DEVICES_PATH))
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        let devices =
+            serde_json::to_string(paired.devices()).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(devices))
+    }
+
+    pub fn register_message_type(mut cx: FunctionContext) -> JsResult<JsString> {
+        let definition = cx.argument::<JsString>(0)?.value();
+        let definition = MessageRegistry::register_json(&definition)
+            .or_else(|e| cx.throw_type_error(e.to_string()))?;
+        let definition =
+            serde_json::to_string(&definition).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(definition))
+    }
+
+    pub fn list_message_types(mut cx: FunctionContext) -> JsResult<JsString> {
+        let definitions = serde_json::to_string(&MessageRegistry::definitions())
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(definitions))
+    }
+
+    pub fn get_schemas(mut cx: FunctionContext) -> JsResult<JsString> {
+        let schemas =
+            serde_json::to_string(Schemas::all()).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(schemas))
+    }
+
+    pub fn write_schemas(mut cx: FunctionContext) -> JsResult<JsString> {
+        let dir = cx.argument::<JsString>(0)?.value();
+        let written = Schemas::write(&dir).or_else(|e| cx.throw_error(e.to_string()))?;
+        let written = serde_json::to_string(&written).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(written))
+    }
+
+    pub fn set_strict_validation(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let strict = cx.argument::<JsBoolean>(0)?.value();
+        Schemas::set_strict(strict);
+        Ok(cx.boolean(strict))
+    }
+
+    pub fn send_message(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let name = cx.argument::<JsString>(1)?.value();
+        let payload = cx.argument::<JsString>(2)?.value();
+        let payload: serde_json::Value =
+            serde_json::from_str(&payload).or_else(|e| cx.throw_type_error(e.to_string()))?;
+        let mut port =
+            SerialData::open_port(SETTINGS, &path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let status = MessageRegistry::send(port.borrow_mut(), &name, payload)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        match status {
+            AxonMessageStatus::Success => Ok(cx.boolean(true)),
+            AxonMessageStatus::Failure => Ok(cx.boolean(false)),
+        }
+    }
+
+    // resolves to the payload as a JSON string, or null when the device only
+    // logged.
+    pub fn recieve_message(mut cx: FunctionContext) -> JsResult<JsValue> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let name = cx.argument::<JsString>(1)?.value();
+        let mut port =
+            SerialData::open_port(SETTINGS, &path).or_else(|e| cx.throw_error(e.to_string()))?;
+        let message = MessageRegistry::recieve(port.borrow_mut(), &name)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        match message {
+            Message::AxonMessage(payload) => {
+                let payload =
+                    serde_json::to_string(&payload).or_else(|e| cx.throw_error(e.to_string()))?;
+                Ok(cx.string(payload).upcast())
+            }
+            _ => Ok(cx.null().upcast()),
+        }
+    }
+
     pub fn axon_init(mut cx: FunctionContext) -> JsResult<JsBoolean> {
         AxonInit::init_fs().or_else(|e| cx.throw_error(e.to_string()))?;
         Ok(cx.boolean(true))
     }
+
+    fn string_field(
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        key: &str,
+    ) -> NeonResult<Option<String>> {
+        let value = obj.get(cx, key)?;
+        if value.is_a::<JsUndefined>() || value.is_a::<JsNull>() {
+            return Ok(None);
+        }
+        match value.downcast::<JsString>() {
+            Ok(value) => Ok(Some(value.value())),
+            Err(_) => cx.throw_type_error(format!("`{}` must be a string", key)),
+        }
+    }
+
+    fn integer_field(
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        key: &str,
+    ) -> NeonResult<Option<u64>> {
+        let value = obj.get(cx, key)?;
+        if value.is_a::<JsUndefined>() || value.is_a::<JsNull>() {
+            return Ok(None);
+        }
+        let value = match value.downcast::<JsNumber>() {
+            Ok(value) => value.value(),
+            Err(_) => return cx.throw_type_error(format!("`{}` must be a number", key)),
+        };
+        if value.fract() != 0.0 || value < 0.0 || value > MAX_SAFE_INTEGER as f64 {
+            return cx.throw_range_error(format!(
+                "`{}` must be an integer between 0 and {}",
+                key, MAX_SAFE_INTEGER
+            ));
+        }
+        Ok(Some(value as u64))
+    }
+
+    fn record_query(cx: &mut FunctionContext, obj: Handle<JsObject>) -> NeonResult<RecordQuery> {
+        Ok(RecordQuery {
+            device_id: string_field(cx, obj, "deviceId")?,
+            sensor: string_field(cx, obj, "sensor")?,
+            from: integer_field(cx, obj, "from")?,
+            to: integer_field(cx, obj, "to")?,
+            limit: integer_field(cx, obj, "limit")?.map(|limit| limit as usize),
+        })
+    }
+
+    // stored records as a JSON array, oldest first. `from` and `to` are epoch
+    // milliseconds compared against when the host received each record.
+    pub fn query_records(mut cx: FunctionContext) -> JsResult<JsString> {
+        let query = match cx.argument_opt(0) {
+            Some(query) if query.is_a::<JsObject>() => {
+                let query = query.downcast::<JsObject>().or_throw(&mut cx)?;
+                record_query(&mut cx, query)?
+            }
+            _ => RecordQuery::default(),
+        };
+        let records = RecordHistory::query(&query).or_else(|e| cx.throw_error(e.to_string()))?;
+        let records = serde_json::to_string(&records).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(records))
+    }
+
+    // passing no path stops storing records.
+    pub fn set_record_store(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let path = match cx.argument_opt(0) {
+            Some(path) if path.is_a::<JsString>() => {
+                path.downcast::<JsString>().or_throw(&mut cx)?.value()
+            }
+            _ => {
+                RecordHistory::set_store(None);
+                return Ok(cx.boolean(false));
+            }
+        };
+        let max_age_days = match cx.argument_opt(1) {
+            Some(_) => integer_argument(&mut cx, 1, MAX_SAFE_INTEGER)?,
+            None => DEFAULT_MAX_AGE_DAYS,
+        };
+        let max_bytes = match cx.argument_opt(2) {
+            Some(_) => integer_argument(&mut cx, 2, MAX_SAFE_INTEGER)?,
+            None => DEFAULT_MAX_BYTES,
+        };
+        RecordHistory::set_store(Some(RecordStore::new(path, max_age_days, max_bytes)));
+        Ok(cx.boolean(true))
+    }
+
+    pub fn load_rules(mut cx: FunctionContext) -> JsResult<JsNumber> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let count = Rules::load(&path).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.number(count as f64))
+    }
+
+    // same JSON array as a rules file, replaces every loaded rule.
+    pub fn set_rules(mut cx: FunctionContext) -> JsResult<JsNumber> {
+        let rules = cx.argument::<JsString>(0)?.value();
+        let rules = RuleEngine::parse(&rules).or_else(|e| cx.throw_type_error(e.to_string()))?;
+        let count = rules.len();
+        Rules::set(rules);
+        Ok(cx.number(count as f64))
+    }
+
+    pub fn list_rules(mut cx: FunctionContext) -> JsResult<JsString> {
+        let rules =
+            serde_json::to_string(&Rules::list()).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(rules))
+    }
+
+    // rule hits newer than `cursor` as JSON, pass the last id seen to get only
+    // new ones.
+    pub fn rule_hits(mut cx: FunctionContext) -> JsResult<JsString> {
+        let cursor = match cx.argument_opt(0) {
+            Some(_) => integer_argument(&mut cx, 0, MAX_SAFE_INTEGER)?,
+            None => 0,
+        };
+        let hits = serde_json::to_string(&Rules::hits_since(cursor))
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(hits))
+    }
+
+    fn schedule_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<Schedule> {
+        let schedule = cx.argument::<JsString>(i)?.value();
+        serde_json::from_str(&schedule).or_else(|e| cx.throw_type_error(e.to_string()))
+    }
+
+    pub fn add_schedule(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let schedule = schedule_argument(&mut cx, 0)?;
+        Schedules::add(&String::from(crate::SCHEDULES_PATH), schedule)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(true))
+    }
+
+    pub fn update_schedule(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let schedule = schedule_argument(&mut cx, 0)?;
+        Schedules::update(&String::from(crate::SCHEDULES_PATH), schedule)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(true))
+    }
+
+    pub fn remove_schedule(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let name = cx.argument::<JsString>(0)?.value();
+        let removed = Schedules::remove(&String::from(crate::SCHEDULES_PATH), &name)
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.boolean(removed))
+    }
+
+    // every schedule with the outcome of its last run, as JSON.
+    pub fn list_schedules(mut cx: FunctionContext) -> JsResult<JsString> {
+        let schedules = Schedules::list(&String::from(crate::SCHEDULES_PATH))
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        let schedules =
+            serde_json::to_string(&schedules).or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(schedules))
+    }
+
+    pub fn start_scheduler(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        Ok(cx.boolean(Scheduler::start(String::from(crate::SCHEDULES_PATH))))
+    }
+
+    pub fn stop_scheduler(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        Scheduler::stop();
+        Ok(cx.undefined())
+    }
+
+    pub struct ExportTask {
+        store: RecordStore,
+        query: RecordQuery,
+        format: ExportFormat,
+        path: String,
+    }
+
+    impl Task for ExportTask {
+        type Output = u64;
+        type Error = String;
+        type JsEvent = JsNumber;
+
+        fn perform(&self) -> Result<Self::Output, Self::Error> {
+            RecordExport::to_file(&self.store, &self.query, &self.format, &self.path)
+                .map_err(|e| e.to_string())
+        }
+
+        fn complete(
+            self,
+            mut cx: TaskContext,
+            result: Result<Self::Output, Self::Error>,
+        ) -> JsResult<Self::JsEvent> {
+            let rows = result.or_else(|e| cx.throw_error(e))?;
+            Ok(cx.number(rows as f64))
+        }
+    }
+
+    // writes the stored records matching `options` to `path` off the main
+    // thread, the callback gets the number of rows. `options.format` is "csv"
+    // (the default) or "ndjson", `options.columns` picks the CSV columns.
+    pub fn export_records(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let path = cx.argument::<JsString>(0)?.value();
+        let options = cx.argument::<JsObject>(1)?;
+        let callback = cx.argument::<JsFunction>(2)?;
+        let query = record_query(&mut cx, options)?;
+        let columns = options.get(&mut cx, "columns")?;
+        let columns = if columns.is_a::<JsArray>() {
+            let names = columns
+                .downcast::<JsArray>()
+                .or_throw(&mut cx)?
+                .to_vec(&mut cx)?;
+            let mut columns = Vec::new();
+            for name in names {
+                let name = name.downcast::<JsString>().or_throw(&mut cx)?.value();
+                match Column::parse(&name) {
+                    Some(column) => columns.push(column),
+                    None => return cx.throw_type_error(format!("unknown column `{}`", name)),
+                }
+            }
+            columns
+        } else {
+            Column::DEFAULT.to_vec()
+        };
+        let format = match string_field(&mut cx, options, "format")?.as_deref() {
+            None | Some("csv") => ExportFormat::Csv(columns),
+            Some("ndjson") => ExportFormat::Ndjson,
+            Some(format) => {
+                return cx.throw_type_error(format!("unknown export format `{}`", format))
+            }
+        };
+        let store = RecordHistory::current().or_else(|e| cx.throw_error(e.to_string()))?;
+        ExportTask {
+            store,
+            query,
+            format,
+            path,
+        }
+        .schedule(callback);
+        Ok(cx.undefined())
+    }
+
+    pub struct DeviceEventTask {
+        events: Arc<Mutex<Receiver<DeviceEvent>>>,
+    }
+
+    impl Task for DeviceEventTask {
+        type Output = Option<DeviceEvent>;
+        type Error = String;
+        type JsEvent = JsValue;
+
+        fn perform(&self) -> Result<Self::Output, Self::Error> {
+            let events = self.events.lock().map_err(|e| e.to_string())?;
+            match events.recv_timeout(EVENT_POLL) {
+                Ok(event) => Ok(Some(event)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err(String::from("supervisor stopped")),
+            }
+        }
+
+        fn complete(
+            self,
+            mut cx: TaskContext,
+            result: Result<Self::Output, Self::Error>,
+        ) -> JsResult<Self::JsEvent> {
+            let event = match result.or_else(|e| cx.throw_error(e))? {
+                Some(event) => event,
+                None => return Ok(cx.undefined().upcast()),
+            };
+            let event_obj = JsObject::new(&mut cx);
+            let (name, data) = match event {
+                DeviceEvent::Connected { path } => {
+                    ("connected", cx.string(path).upcast::<JsValue>())
+                }
+                DeviceEvent::Disconnected { path } => {
+                    ("disconnected", cx.string(path).upcast::<JsValue>())
+                }
+                DeviceEvent::Rejected { path, reason } => {
+                    let rejected_obj = JsObject::new(&mut cx);
+                    let path = cx.string(path);
+                    let reason = cx.string(reason);
+                    rejected_obj.set(&mut cx, "path", path)?;
+                    rejected_obj.set(&mut cx, "reason", reason)?;
+                    ("rejected", rejected_obj.upcast::<JsValue>())
+                }
+                DeviceEvent::Record(record) => ("record", cx.string(record).upcast::<JsValue>()),
+                DeviceEvent::State(status) => ("state", cx.boolean(status).upcast::<JsValue>()),
+                DeviceEvent::ClockSync(clock) => {
+                    ("clockSync", cx.string(clock).upcast::<JsValue>())
+                }
+            };
+            let name = cx.string(name);
+            event_obj.set(&mut cx, "event", name)?;
+            event_obj.set(&mut cx, "data", data)?;
+            Ok(event_obj.upcast())
+        }
+    }
+
+    pub fn device_logs(mut cx: FunctionContext) -> JsResult<JsString> {
+        let cursor = match cx.argument_opt(0) {
+            Some(_) => integer_argument(&mut cx, 0, MAX_SAFE_INTEGER)?,
+            None => 0,
+        };
+        let logs = serde_json::to_string(&DeviceLogs::since(cursor))
+            .or_else(|e| cx.throw_error(e.to_string()))?;
+        Ok(cx.string(logs))
+    }
+
+    // passing no path turns the log file off again.
+    pub fn set_device_log_file(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+        let path = match cx.argument_opt(0) {
+            Some(path) if path.is_a::<JsString>() => {
+                path.downcast::<JsString>().or_throw(&mut cx)?.value()
+            }
+            _ => {
+                DeviceLogs::set_file(None);
+                return Ok(cx.boolean(false));
+            }
+        };
+        let max_bytes = match cx.argument_opt(1) {
+            Some(_) => integer_argument(&mut cx, 1, MAX_SAFE_INTEGER)?,
+            None => LOG_FILE_MAX_BYTES,
+        };
+        let max_files = match cx.argument_opt(2) {
+            Some(_) => integer_argument(&mut cx, 2, u64::from(u32::MAX))? as u32,
+            None => LOG_FILE_COUNT,
+        };
+        DeviceLogs::set_file(Some(RotatingFile::new(path, max_bytes, max_files)));
+        Ok(cx.boolean(true))
+    }
+
+    pub struct DeviceLogTask {
+        cursor: u64,
+    }
+
+    impl Task for DeviceLogTask {
+        type Output = Vec<DeviceLog>;
+        type Error = String;
+        type JsEvent = JsString;
+
+        fn perform(&self) -> Result<Self::Output, Self::Error> {
+            Ok(DeviceLogs::wait(self.cursor, EVENT_POLL))
+        }
+
+        fn complete(
+            self,
+            mut cx: TaskContext,
+            result: Result<Self::Output, Self::Error>,
+        ) -> JsResult<Self::JsEvent> {
+            let logs = result.or_else(|e| cx.throw_error(e))?;
+            let logs = serde_json::to_string(&logs).or_else(|e| cx.throw_error(e.to_string()))?;
+            Ok(cx.string(logs))
+        }
+    }
+
+    // calls back with every device log newer than `cursor`, or an empty list
+    // when nothing arrived in time. the last id seen is the next cursor.
+    pub fn poll_device_logs(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let cursor = integer_argument(&mut cx, 0, MAX_SAFE_INTEGER)?;
+        let callback = cx.argument::<JsFunction>(1)?;
+        DeviceLogTask { cursor }.schedule(callback);
+        Ok(cx.undefined())
+    }
+
+    pub fn set_log_level(mut cx: FunctionContext) -> JsResult<JsString> {
+        let level = cx.argument::<JsString>(0)?.value();
+        let level = HostLog::set_level(&level).or_else(|e| cx.throw_type_error(e.to_string()))?;
+        Ok(cx.string(level.to_string().to_lowercase()))
+    }
+
+    pub fn log_level(mut cx: FunctionContext) -> JsResult<JsString> {
+        Ok(cx.string(HostLog::level().to_string().to_lowercase()))
+    }
+
+    pub struct HostLogTask {
+        cursor: u64,
+    }
+
+    impl Task for HostLogTask {
+        type Output = Vec<LogRecord>;
+        type Error = String;
+        type JsEvent = JsString;
+
+        fn perform(&self) -> Result<Self::Output, Self::Error> {
+            Ok(HostLog::wait(self.cursor, EVENT_POLL))
+        }
+
+        fn complete(
+            self,
+            mut cx: TaskContext,
+            result: Result<Self::Output, Self::Error>,
+        ) -> JsResult<Self::JsEvent> {
+            let records = result.or_else(|e| cx.throw_error(e))?;
+            let records =
+                serde_json::to_string(&records).or_else(|e| cx.throw_error(e.to_string()))?;
+            Ok(cx.string(records))
+        }
+    }
+
+    // same cursor scheme as `pollDeviceLogs`, for the host's own log records.
+    pub fn poll_logs(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let cursor = integer_argument(&mut cx, 0, MAX_SAFE_INTEGER)?;
+        let callback = cx.argument::<JsFunction>(1)?;
+        HostLogTask { cursor }.schedule(callback);
+        Ok(cx.undefined())
+    }
+
+    declare_types! {
+        pub class JsDeviceSupervisor for DeviceSupervisor {
+            init(mut cx) {
+                let serial_number = cx.argument::<JsString>(0)?.value();
+                Ok(DeviceSupervisor::spawn(serial_number, SETTINGS, SUPERVISOR_POLL))
+            }
+
+            method poll(mut cx) {
+                let callback = cx.argument::<JsFunction>(0)?;
+                let this = cx.this();
+                let events = {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.events.clone()
+                };
+                DeviceEventTask { events }.schedule(callback);
+                Ok(cx.undefined().upcast())
+            }
+
+            method watchRecords(mut cx) {
+                let this = cx.this();
+                {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.spawn_stream(|port| {
+                        let mut record = Record::watch_port(port)?;
+                        RecordHistory::store(&record);
+                        Rules::apply(port, &record);
+                        record.fill_readings()?;
+                        Ok(Some(DeviceEvent::Record(record.to_json_string()?)))
+                    });
+                }
+                Ok(cx.undefined().upcast())
+            }
+
+            method watchState(mut cx) {
+                let this = cx.this();
+                {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.spawn_stream(|port| {
+                        let status = State::watch_state_port(&String::from(crate::STATE_PATH), port)?;
+                        Ok(Some(DeviceEvent::State(status)))
+                    });
+                }
+                Ok(cx.undefined().upcast())
+            }
+
+            method syncClock(mut cx) {
+                let interval = cx.argument::<JsNumber>(0)?.value();
+                if interval.fract() != 0.0 || interval < 1.0 || interval > MAX_SAFE_INTEGER as f64 {
+                    return cx.throw_range_error("interval must be a positive number of milliseconds");
+                }
+                let this = cx.this();
+                {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.spawn_periodic(CLOCK_SYNC_TASK, Duration::from_millis(interval as u64), |port| {
+                        let clock = TimeSync::sync_port(port)?;
+                        Ok(Some(DeviceEvent::ClockSync(serde_json::to_string(&clock)?)))
+                    });
+                }
+                Ok(cx.undefined().upcast())
+            }
+
+            method stopClockSync(mut cx) {
+                let this = cx.this();
+                let stopped = {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.stop_periodic(CLOCK_SYNC_TASK)
+                };
+                Ok(cx.boolean(stopped).upcast())
+            }
+
+            method capabilities(mut cx) {
+                let this = cx.this();
+                let negotiation = {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.negotiation()
+                };
+                match negotiation {
+                    Some(negotiation) => {
+                        let negotiation = serde_json::to_string(&negotiation)
+                            .or_else(|e| cx.throw_error(e.to_string()))?;
+                        Ok(cx.string(negotiation).upcast())
+                    }
+                    None => Ok(cx.null().upcast()),
+                }
+            }
+
+            method isConnected(mut cx) {
+                let this = cx.this();
+                let connected = {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.is_connected()
+                };
+                Ok(cx.boolean(connected).upcast())
+            }
+
+            method shutdown(mut cx) {
+                let this = cx.this();
+                {
+                    let guard = cx.lock();
+                    let supervisor = this.borrow(&guard);
+                    supervisor.shutdown();
+                }
+                Ok(cx.undefined().upcast())
+            }
+        }
+    }
 }
@@ -0,0 +1,238 @@
+pub mod capability {
+
+    use crate::codec::codec::Codec;
+    use crate::handshake::handshake::AxonMessageType;
+    use crate::registry::message_registry::MessageRegistry;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::io::{Error, ErrorKind};
+    use std::sync::Mutex;
+
+    // v1 is the original handshake, which carried no version or capabilities.
+    // v3 moved every frame into a typed `Envelope`, which older firmware can't
+    // produce.
+    pub const LEGACY_PROTOCOL_VERSION: u8 = 1;
+    pub const MIN_PROTOCOL_VERSION: u8 = 3;
+    pub const PROTOCOL_VERSION: u8 = 3;
+    pub const MAX_FRAME_SIZE: u32 = 1024;
+
+    static NEGOTIATED: Mutex<BTreeMap<String, Negotiation>> = Mutex::new(BTreeMap::new());
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub enum FramingMode {
+        JsonLine,
+        Envelope,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Default)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Capabilities {
+        #[serde(default)]
+        pub versions: Vec<u8>,
+        #[serde(default)]
+        pub message_types: Vec<AxonMessageType>,
+        #[serde(default)]
+        pub framing: Vec<FramingMode>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub max_frame_size: Option<u32>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub pins: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub sensors: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub codecs: Vec<Codec>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Negotiation {
+        pub version: u8,
+        pub device: Capabilities,
+        // handshakes are always JSON, every frame after the accept uses this.
+        #[serde(default)]
+        pub codec: Codec,
+    }
+
+    impl Capabilities {
+        pub fn host() -> Capabilities {
+            let mut message_types = vec![
+                AxonMessageType::RecordMessage,
+                AxonMessageType::StateMessage,
+                AxonMessageType::CommandMessage,
+                AxonMessageType::QueryMessage,
+                AxonMessageType::CapabilityMessage,
+                AxonMessageType::PairMessage,
+                AxonMessageType::TimeSyncMessage,
+            ];
+            message_types.extend(
+                MessageRegistry::definitions()
+                    .iter()
+                    .map(|definition| AxonMessageType::Custom(definition.id)),
+            );
+            Capabilities {
+                versions: (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).collect(),
+                message_types,
+                framing: vec![FramingMode::Envelope],
+                max_frame_size: Some(MAX_FRAME_SIZE),
+                pins: Vec::new(),
+                sensors: Vec::new(),
+                codecs: Codec::PREFERENCE.to_vec(),
+            }
+        }
+
+        pub fn supports(&self, message_type: &AxonMessageType) -> bool {
+            self.message_types.is_empty() || self.message_types.contains(message_type)
+        }
+    }
+
+    impl Negotiation {
+        // without a capability list the handshake's own `version` is the only
+        // one offered, and a device that names neither is legacy firmware.
+        pub fn negotiate(
+            device: Option<Capabilities>,
+            version: Option<u8>,
+        ) -> Result<Negotiation, Error> {
+            let device = device.unwrap_or_default();
+            let host = Capabilities::host();
+            let offered = if device.versions.is_empty() {
+                vec![version.unwrap_or(LEGACY_PROTOCOL_VERSION)]
+            } else {
+                device.versions.clone()
+            };
+            let version = offered
+                .iter()
+                .filter(|version| host.versions.contains(version))
+                .max()
+                .cloned();
+            match version {
+                Some(version) => Ok(Negotiation {
+                    version,
+                    codec: Codec::select(&device.codecs),
+                    device,
+                }),
+                None => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "no common protocol version, host supports {:?} and device {:?}",
+                        host.versions, offered
+                    ),
+                )),
+            }
+        }
+
+        // the accepting side names the codec it picked, which has to be one
+        // both ends speak. without one each side works it out the same way.
+        pub fn with_codec(mut self, codec: Option<Codec>) -> Result<Negotiation, Error> {
+            match codec {
+                None => Ok(self),
+                Some(codec)
+                    if Codec::PREFERENCE.contains(&codec)
+                        && (codec == Codec::Json || self.device.codecs.contains(&codec)) =>
+                {
+                    self.codec = codec;
+                    Ok(self)
+                }
+                Some(codec) => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("device picked {:?}, which it didn't offer", codec),
+                )),
+            }
+        }
+
+        pub fn check(&self, message_type: &AxonMessageType) -> Result<(), Error> {
+            if self.device.supports(message_type) {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("device doesn't support {:?} messages", message_type),
+                ))
+            }
+        }
+
+        // the smaller of the two limits, a device that names none gets the host's.
+        pub fn max_frame_size(&self) -> u32 {
+            self.device
+                .max_frame_size
+                .map_or(MAX_FRAME_SIZE, |size| size.min(MAX_FRAME_SIZE))
+        }
+
+        pub fn store(port_name: &str, negotiation: &Negotiation) {
+            NEGOTIATED
+                .lock()
+                .unwrap()
+                .insert(port_name.to_string(), negotiation.clone());
+        }
+
+        pub fn load(port_name: &str) -> Option<Negotiation> {
+            NEGOTIATED.lock().unwrap().get(port_name).cloned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capability::capability::{
+        Capabilities, Negotiation, MAX_FRAME_SIZE, PROTOCOL_VERSION,
+    };
+    use crate::codec::codec::Codec;
+    use crate::handshake::handshake::AxonMessageType;
+
+    #[test]
+    fn negotiate_versions() {
+        assert!(Negotiation::negotiate(None, None).is_err());
+        assert_eq!(
+            Negotiation::negotiate(None, Some(PROTOCOL_VERSION))
+                .unwrap()
+                .version,
+            PROTOCOL_VERSION
+        );
+        let device = Capabilities {
+            versions: vec![1, 3, 7],
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            Negotiation::negotiate(Some(device), None).unwrap().version,
+            PROTOCOL_VERSION
+        );
+        let future = Capabilities {
+            versions: vec![7, 8],
+            ..Capabilities::default()
+        };
+        assert!(Negotiation::negotiate(Some(future), None).is_err());
+        let compact = Capabilities {
+            versions: vec![3],
+            codecs: vec![Codec::Json, Codec::Cbor],
+            ..Capabilities::default()
+        };
+        let negotiation = Negotiation::negotiate(Some(compact), None).unwrap();
+        assert_eq!(negotiation.codec, Codec::Cbor);
+        assert_eq!(
+            negotiation
+                .clone()
+                .with_codec(Some(Codec::Json))
+                .unwrap()
+                .codec,
+            Codec::Json
+        );
+        assert!(negotiation
+            .clone()
+            .with_codec(Some(Codec::MessagePack))
+            .is_err());
+        assert_eq!(negotiation.max_frame_size(), MAX_FRAME_SIZE);
+        assert!(negotiation.check(&AxonMessageType::QueryMessage).is_ok());
+
+        let small = Capabilities {
+            versions: vec![3],
+            message_types: vec![AxonMessageType::RecordMessage],
+            max_frame_size: Some(256),
+            ..Capabilities::default()
+        };
+        let negotiation = Negotiation::negotiate(Some(small), None).unwrap();
+        assert_eq!(negotiation.max_frame_size(), 256);
+        assert!(negotiation.check(&AxonMessageType::RecordMessage).is_ok());
+        assert!(negotiation.check(&AxonMessageType::CommandMessage).is_err());
+    }
+}
@@ -0,0 +1,466 @@
+pub mod capture {
+
+    use crate::sequence::sequence::SequenceTracker;
+    use crate::serial::serial_handler::SerialData;
+    use serde::{Deserialize, Serialize};
+    use serialport::prelude::*;
+    use serialport::{ClearBuffer, Error as SerialError, ErrorKind as SerialErrorKind};
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::io::Result as SingleResult;
+    use std::io::{BufReader, Error, ErrorKind};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    static CAPTURE: Mutex<Option<CaptureWriter>> = Mutex::new(None);
+
+    // replayed ports are named after their capture file with this in front.
+    pub const REPLAY_PREFIX: &str = "replay:";
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub enum Direction {
+        In,
+        Out,
+    }
+
+    // one line of a capture file. `at` is milliseconds since the capture
+    // started and `data` the exact bytes, hex encoded.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct CaptureEntry {
+        pub at: u64,
+        pub port: String,
+        pub direction: Direction,
+        pub data: String,
+    }
+
+    #[derive(Clone)]
+    pub struct CaptureWriter {
+        file: Arc<Mutex<File>>,
+        started: Instant,
+    }
+
+    // passes everything through to the real port and copies the bytes that
+    // cross it into the capture file.
+    pub struct CapturePort {
+        inner: Box<dyn SerialPort>,
+        writer: CaptureWriter,
+    }
+
+    // plays the inbound side of a capture back to whatever reads from it. writes
+    // are accepted and kept, but not checked against the capture, since the
+    // host's challenges and sequence numbers differ on every run.
+    pub struct ReplayPort {
+        name: String,
+        settings: SerialPortSettings,
+        inbound: VecDeque<u8>,
+        written: Vec<u8>,
+    }
+
+    impl CaptureWriter {
+        pub fn create(path: &String) -> SingleResult<CaptureWriter> {
+            Ok(CaptureWriter {
+                file: Arc::new(Mutex::new(File::create(path)?)),
+                started: Instant::now(),
+            })
+        }
+
+        fn record(&self, port: &str, direction: Direction, data: &[u8]) -> SingleResult<()> {
+            if data.is_empty() {
+                return Ok(());
+            }
+            let entry = CaptureEntry {
+                at: self.started.elapsed().as_millis() as u64,
+                port: port.to_string(),
+                direction,
+                data: hex::encode(data),
+            };
+            let mut line = serde_json::to_string(&entry)?;
+            line.push('\n');
+            self.file.lock().unwrap().write_all(line.as_bytes())
+        }
+    }
+
+    pub struct Capture;
+
+    impl Capture {
+        // ports opened from now on are captured to `path`, until `stop`.
+        pub fn start(path: &String) -> SingleResult<()> {
+            *CAPTURE.lock().unwrap() = Some(CaptureWriter::create(path)?);
+            Ok(())
+        }
+
+        pub fn stop() -> bool {
+            CAPTURE.lock().unwrap().take().is_some()
+        }
+
+        pub fn is_capturing() -> bool {
+            CAPTURE.lock().unwrap().is_some()
+        }
+
+        pub fn wrap(port: Box<dyn SerialPort>) -> Box<dyn SerialPort> {
+            match CAPTURE.lock().unwrap().as_ref() {
+                Some(writer) => Box::new(CapturePort {
+                    inner: port,
+                    writer: writer.clone(),
+                }),
+                None => port,
+            }
+        }
+
+        pub fn load(path: &String) -> SingleResult<Vec<CaptureEntry>> {
+            let reader = BufReader::new(File::open(path)?);
+            let mut entries = Vec::new();
+            for line in reader.lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    entries.push(serde_json::from_str(&line)?);
+                }
+            }
+            Ok(entries)
+        }
+    }
+
+    impl ReplayPort {
+        pub fn new(name: String, entries: &[CaptureEntry]) -> SingleResult<ReplayPort> {
+            let mut inbound = VecDeque::new();
+            for entry in entries
+                .iter()
+                .filter(|entry| entry.direction == Direction::In)
+            {
+                let data = hex::decode(&entry.data)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+                inbound.extend(data);
+            }
+            Ok(ReplayPort {
+                name,
+                settings: SerialPortSettings::default(),
+                inbound,
+                written: Vec::new(),
+            })
+        }
+
+        pub fn open(path: &String) -> SingleResult<Box<dyn SerialPort>> {
+            let entries = Capture::load(path)?;
+            let name = format!("{}{}", REPLAY_PREFIX, path);
+            // every replay starts from the beginning of the device's sequence.
+            SequenceTracker::reset(&name);
+            SerialData::clear_pending(&name);
+            Ok(Box::new(Self::new(name, &entries)?))
+        }
+
+        pub fn is_replay(port_name: &str) -> bool {
+            port_name.starts_with(REPLAY_PREFIX)
+        }
+
+        pub fn written(&self) -> &[u8] {
+            &self.written
+        }
+    }
+
+    impl Read for CapturePort {
+        fn read(&mut self, buf: &mut [u8]) -> SingleResult<usize> {
+            let read = self.inner.read(buf)?;
+            let name = self.inner.name().unwrap_or_default();
+            self.writer.record(&name, Direction::In, &buf[..read])?;
+            Ok(read)
+        }
+    }
+
+    impl Write for CapturePort {
+        fn write(&mut self, buf: &[u8]) -> SingleResult<usize> {
+            let written = self.inner.write(buf)?;
+            let name = self.inner.name().unwrap_or_default();
+            self.writer.record(&name, Direction::Out, &buf[..written])?;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> SingleResult<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl SerialPort for CapturePort {
+        fn name(&self) -> Option<String> {
+            self.inner.name()
+        }
+
+        fn settings(&self) -> SerialPortSettings {
+            self.inner.settings()
+        }
+
+        fn baud_rate(&self) -> serialport::Result<u32> {
+            self.inner.baud_rate()
+        }
+
+        fn data_bits(&self) -> serialport::Result<DataBits> {
+            self.inner.data_bits()
+        }
+
+        fn flow_control(&self) -> serialport::Result<FlowControl> {
+            self.inner.flow_control()
+        }
+
+        fn parity(&self) -> serialport::Result<Parity> {
+            self.inner.parity()
+        }
+
+        fn stop_bits(&self) -> serialport::Result<StopBits> {
+            self.inner.stop_bits()
+        }
+
+        fn timeout(&self) -> Duration {
+            self.inner.timeout()
+        }
+
+        fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+            self.inner.set_all(settings)
+        }
+
+        fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+            self.inner.set_baud_rate(baud_rate)
+        }
+
+        fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+            self.inner.set_data_bits(data_bits)
+        }
+
+        fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+            self.inner.set_flow_control(flow_control)
+        }
+
+        fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+            self.inner.set_parity(parity)
+        }
+
+        fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+            self.inner.set_stop_bits(stop_bits)
+        }
+
+        fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+            self.inner.set_timeout(timeout)
+        }
+
+        fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+            self.inner.write_request_to_send(level)
+        }
+
+        fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+            self.inner.write_data_terminal_ready(level)
+        }
+
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+            self.inner.read_clear_to_send()
+        }
+
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+            self.inner.read_data_set_ready()
+        }
+
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+            self.inner.read_ring_indicator()
+        }
+
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+            self.inner.read_carrier_detect()
+        }
+
+        fn bytes_to_read(&self) -> serialport::Result<u32> {
+            self.inner.bytes_to_read()
+        }
+
+        fn bytes_to_write(&self) -> serialport::Result<u32> {
+            self.inner.bytes_to_write()
+        }
+
+        fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+            self.inner.clear(buffer_to_clear)
+        }
+
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+            Ok(Box::new(CapturePort {
+                inner: self.inner.try_clone()?,
+                writer: self.writer.clone(),
+            }))
+        }
+    }
+
+    impl Read for ReplayPort {
+        // hands out at most one line per read. `SerialData::read_port` builds a
+        // fresh reader for every line, so anything read past the newline would be
+        // lost.
+        fn read(&mut self, buf: &mut [u8]) -> SingleResult<usize> {
+            if self.inbound.is_empty() {
+                return Err(Error::new(ErrorKind::TimedOut, "capture exhausted"));
+            }
+            let line = self
+                .inbound
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map(|end| end + 1)
+                .unwrap_or_else(|| self.inbound.len());
+            let count = line.min(buf.len());
+            for (slot, byte) in buf.iter_mut().zip(self.inbound.drain(..count)) {
+                *slot = byte;
+            }
+            Ok(count)
+        }
+    }
+
+    impl Write for ReplayPort {
+        fn write(&mut self, buf: &[u8]) -> SingleResult<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> SingleResult<()> {
+            Ok(())
+        }
+    }
+
+    impl SerialPort for ReplayPort {
+        fn name(&self) -> Option<String> {
+            Some(self.name.clone())
+        }
+
+        fn settings(&self) -> SerialPortSettings {
+            self.settings
+        }
+
+        fn baud_rate(&self) -> serialport::Result<u32> {
+            Ok(self.settings.baud_rate)
+        }
+
+        fn data_bits(&self) -> serialport::Result<DataBits> {
+            Ok(self.settings.data_bits)
+        }
+
+        fn flow_control(&self) -> serialport::Result<FlowControl> {
+            Ok(self.settings.flow_control)
+        }
+
+        fn parity(&self) -> serialport::Result<Parity> {
+            Ok(self.settings.parity)
+        }
+
+        fn stop_bits(&self) -> serialport::Result<StopBits> {
+            Ok(self.settings.stop_bits)
+        }
+
+        fn timeout(&self) -> Duration {
+            self.settings.timeout
+        }
+
+        fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+            self.settings = *settings;
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+            self.settings.baud_rate = baud_rate;
+            Ok(())
+        }
+
+        fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+            self.settings.data_bits = data_bits;
+            Ok(())
+        }
+
+        fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+            self.settings.flow_control = flow_control;
+            Ok(())
+        }
+
+        fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+            self.settings.parity = parity;
+            Ok(())
+        }
+
+        fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+            self.settings.stop_bits = stop_bits;
+            Ok(())
+        }
+
+        fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+            self.settings.timeout = timeout;
+            Ok(())
+        }
+
+        fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+
+        fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+
+        fn bytes_to_read(&self) -> serialport::Result<u32> {
+            Ok(self.inbound.len() as u32)
+        }
+
+        fn bytes_to_write(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+
+        fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+            Ok(())
+        }
+
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+            Err(SerialError::new(
+                SerialErrorKind::Unknown,
+                "a replay can only be read once",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::capture::{Capture, ReplayPort};
+    use crate::record::record::Record;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> String {
+        let mut path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test_files/capture");
+        path.push(name);
+        String::from(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn replay_record_session() {
+        let path = fixture("record_session.ndjson");
+        assert_eq!(Capture::load(&path).unwrap().len(), 4);
+        let mut port = ReplayPort::open(&path).unwrap();
+        // never checked against the paired devices on this machine, the
+        // capture's signature is over a challenge from another run.
+        assert!(ReplayPort::is_replay(&port.name().unwrap()));
+        let record = Record::watch_port(&mut port).unwrap();
+        assert_eq!(
+            serde_json::to_value(&record).unwrap()["sensorName"],
+            "temperature"
+        );
+        let mut rest = Vec::new();
+        assert!(port.read_to_end(&mut rest).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+pub mod codec {
+
+    use schemars::JsonSchema;
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Serialize};
+    use std::io::{Error, ErrorKind};
+
+    // binary frames start with this byte, then the codec id and a big endian
+    // u16 length. JSON frames stay plain text lines.
+    pub const BINARY_MARKER: u8 = 0x02;
+    const BINARY_HEADER_LENGTH: usize = 4;
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub enum Codec {
+        #[default]
+        Json,
+        Cbor,
+        MessagePack,
+    }
+
+    fn invalid<E: ToString>(codec: Codec, error: E) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?}: {}", codec, error.to_string()),
+        )
+    }
+
+    impl Codec {
+        // most compact first. maps keep their field names so firmware can read
+        // them with the same document code it uses for JSON.
+        pub const PREFERENCE: [Codec; 3] = [Codec::MessagePack, Codec::Cbor, Codec::Json];
+
+        pub fn id(&self) -> u8 {
+            match *self {
+                Codec::Json => 0,
+                Codec::Cbor => 1,
+                Codec::MessagePack => 2,
+            }
+        }
+
+        pub fn from_id(id: u8) -> Option<Codec> {
+            Self::PREFERENCE
+                .iter()
+                .find(|codec| codec.id() == id)
+                .cloned()
+        }
+
+        // the host's most preferred codec the device also offers. devices that
+        // list none only speak JSON.
+        pub fn select(offered: &[Codec]) -> Codec {
+            Self::PREFERENCE
+                .iter()
+                .find(|codec| offered.contains(codec))
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+            match *self {
+                Codec::Json => serde_json::to_vec(value).map_err(|e| invalid(*self, e)),
+                Codec::Cbor => {
+                    let mut bytes = Vec::new();
+                    ciborium::ser::into_writer(value, &mut bytes).map_err(|e| invalid(*self, e))?;
+                    Ok(bytes)
+                }
+                Codec::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| invalid(*self, e)),
+            }
+        }
+
+        pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+            match *self {
+                Codec::Json => serde_json::from_slice(bytes).map_err(|e| invalid(*self, e)),
+                Codec::Cbor => ciborium::de::from_reader(bytes).map_err(|e| invalid(*self, e)),
+                Codec::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| invalid(*self, e)),
+            }
+        }
+
+        // the bytes that go on the wire after the prefix.
+        pub fn frame<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+            let body = self.encode(value)?;
+            if *self == Codec::Json {
+                return Ok(body);
+            }
+            if body.len() > usize::from(u16::MAX) {
+                return Err(invalid(
+                    *self,
+                    format!("frame of {} bytes is too long", body.len()),
+                ));
+            }
+            let mut frame = Vec::with_capacity(BINARY_HEADER_LENGTH + body.len());
+            frame.push(BINARY_MARKER);
+            frame.push(self.id());
+            frame.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&body);
+            Ok(frame)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axonmessage::axonmessage::{AxonMessage, Envelope, Frame};
+    use crate::codec::codec::{Codec, BINARY_MARKER};
+    use crate::command::command::Command;
+    use crate::handshake::handshake::{HandshakeRequest, HandshakeResponse};
+    use crate::query::query::{Query, QueryResponse};
+    use crate::record::fixture::record_json;
+    use crate::record::record::Record;
+    use crate::state::device_state::State;
+    use serde_json::{json, Value};
+
+    // every codec has to give back exactly what went in, directly and inside
+    // an envelope.
+    fn round_trip<T: AxonMessage>(message: Value) {
+        let typed: T = serde_json::from_value(message.clone()).unwrap();
+        for codec in Codec::PREFERENCE.iter() {
+            let bytes = typed.encode(*codec).unwrap();
+            let decoded = T::decode(&bytes, *codec).unwrap();
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), message);
+
+            let frame = codec.frame(&Envelope::wrap(&typed, None).unwrap()).unwrap();
+            let envelope = match Envelope::decode_bytes(&frame).unwrap() {
+                Frame::Envelope(envelope) => envelope,
+                Frame::Log(line) => panic!("decoded a log line: {}", line),
+            };
+            assert_eq!(
+                serde_json::to_value(&envelope.open::<T>().unwrap()).unwrap(),
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_messages() {
+        round_trip::<HandshakeRequest>(json!({
+            "handshakeType": 18499,
+            "messageType": 0,
+            "version": 3,
+            "capabilities": {
+                "versions": [3],
+                "messageTypes": [0, 1, 70],
+                "framing": ["envelope"],
+                "maxFrameSize": 1024,
+                "pins": [13],
+                "sensors": ["temperature"],
+                "codecs": ["messagePack", "json"]
+            },
+            "challenge": "c0ffee",
+            "deviceId": "device"
+        }));
+        round_trip::<HandshakeResponse>(json!({
+            "handshakeType": 18497,
+            "version": 3,
+            "deviceId": "device",
+            "publicKey": "ab",
+            "signature": "cd"
+        }));
+        let mut record = record_json("device", "temperature", "21.5");
+        record["recordType"] = json!(78);
+        record["encrypted"] = json!(true);
+        round_trip::<Record>(record);
+        round_trip::<State>(json!({
+            "ownerPublicKey": "A",
+            "nodeIp": "B",
+            "genHash": "C"
+        }));
+        let command = json!({
+            "version": 2,
+            "command": 77,
+            "pin": 9,
+            "value": 0,
+            "currency_amount": 18_446_744_073_709_551_615u64,
+            "mosaic_id": "6BED913FA20223F8",
+            "divisibility": 6
+        });
+        round_trip::<Command>(command.clone());
+        round_trip::<Query>(json!({ "correlationId": 7, "command": command }));
+        round_trip::<QueryResponse>(json!({
+            "correlationId": 7,
+            "result": { "type": "number", "value": -1.5 }
+        }));
+    }
+
+    #[test]
+    fn select_and_frame() {
+        assert_eq!(Codec::select(&[]), Codec::Json);
+        assert_eq!(Codec::select(&[Codec::Json, Codec::Cbor]), Codec::Cbor);
+        let json = Codec::Json.frame(&json!({ "a": 1 })).unwrap();
+        assert_eq!(json, b"{\"a\":1}".to_vec());
+        let packed = Codec::MessagePack.frame(&json!({ "a": 1 })).unwrap();
+        assert_eq!(packed[..4], [BINARY_MARKER, 2, 0, 4]);
+        assert_eq!(packed.len(), 8);
+    }
+}
@@ -2,79 +2,442 @@ pub mod command {
 
     use crate::axonmessage::axonmessage::{AxonMessage, Sendable};
     use crate::handshake::handshake::{AxonMessageStatus, AxonMessageType, Handshake};
+    use crate::schema::message_schema::integer_enum;
     use crate::serial::serial_handler::SerialData;
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::Schema;
+    use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
+    use serde_repr::*;
     use serialport::prelude::*;
     use std::borrow::BorrowMut;
-    use std::io::Error;
+    use std::io::{Error, ErrorKind};
 
-    const COMAMND_PREFIX_BYTE: char = 'C';
+    pub const COMAMND_PREFIX_BYTE: char = 'C';
 
+    // v1 carried `currency_amount` as an i8 and had no `version` field.
+    pub const COMMAND_VERSION: u8 = 2;
+    const MOSAIC_ID_LENGTH: usize = 16;
+    const MAX_DIVISIBILITY: u8 = 6;
+
+    // pins 0 and 1 carry the serial link itself, A0-A5 map to 14-19.
+    pub const MIN_PIN: u8 = 2;
+    pub const MAX_PIN: u8 = 19;
+    pub const PWM_PINS: [u8; 6] = [3, 5, 6, 9, 10, 11];
+    pub const MAX_SERVO_ANGLE: u8 = 180;
+    pub const MAX_PULSE_MILLIS: u32 = 60_000;
+
+    #[derive(Serialize_repr, Deserialize_repr, Debug, PartialEq, Clone, Copy)]
+    #[repr(u8)]
+    pub enum CommandCode {
+        DigitalWrite = 68,
+        AnalogWrite = 65,
+        Pulse = 80,
+        ServoAngle = 83,
+        ReadPin = 82,
+        Dispense = 77,
+    }
+
+    impl CommandCode {
+        pub const ALL: [CommandCode; 6] = [
+            CommandCode::DigitalWrite,
+            CommandCode::AnalogWrite,
+            CommandCode::Pulse,
+            CommandCode::ServoAngle,
+            CommandCode::ReadPin,
+            CommandCode::Dispense,
+        ];
+    }
+
+    impl JsonSchema for CommandCode {
+        fn schema_name() -> String {
+            String::from("CommandCode")
+        }
+
+        fn json_schema(_: &mut SchemaGenerator) -> Schema {
+            integer_enum(CommandCode::ALL.iter().map(|code| *code as i64))
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    #[serde(tag = "op", rename_all = "camelCase")]
+    pub enum Operation {
+        DigitalWrite {
+            pin: u8,
+            value: bool,
+        },
+        AnalogWrite {
+            pin: u8,
+            value: u8,
+        },
+        Pulse {
+            pin: u8,
+            millis: u32,
+        },
+        ServoAngle {
+            pin: u8,
+            angle: u8,
+        },
+        ReadPin {
+            pin: u8,
+        },
+        Dispense {
+            pin: u8,
+            amount: u64,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            mosaic: Option<Mosaic>,
+        },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Mosaic {
+        pub id: String,
+        pub divisibility: u8,
+    }
+
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
     pub struct CommandResponse {
         pub status: bool,
-        pub pin: i8,
-        pub operation: String,
+        pub pin: u8,
+        pub operation: Operation,
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
     pub struct Command {
-        operation: String,
-        command: i8,
-        currency_amount: i8,
-        pin: i8,
+        #[serde(default = "Command::legacy_version")]
+        version: u8,
+        command: CommandCode,
+        pin: u8,
+        value: u32,
+        currency_amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mosaic_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        divisibility: Option<u8>,
     }
 
-    impl AxonMessage for Command {}
+    impl AxonMessage for Command {
+        fn message_type() -> &'static str {
+            "command"
+        }
+    }
     impl Sendable for Command {}
 
+    fn invalid(message: String) -> Error {
+        Error::new(ErrorKind::InvalidInput, message)
+    }
+
+    impl Operation {
+        pub fn pin(&self) -> u8 {
+            match *self {
+                Operation::DigitalWrite { pin, .. }
+                | Operation::AnalogWrite { pin, .. }
+                | Operation::Pulse { pin, .. }
+                | Operation::ServoAngle { pin, .. }
+                | Operation::ReadPin { pin }
+                | Operation::Dispense { pin, .. } => pin,
+            }
+        }
+
+        pub fn code(&self) -> CommandCode {
+            match self {
+                Operation::DigitalWrite { .. } => CommandCode::DigitalWrite,
+                Operation::AnalogWrite { .. } => CommandCode::AnalogWrite,
+                Operation::Pulse { .. } => CommandCode::Pulse,
+                Operation::ServoAngle { .. } => CommandCode::ServoAngle,
+                Operation::ReadPin { .. } => CommandCode::ReadPin,
+                Operation::Dispense { .. } => CommandCode::Dispense,
+            }
+        }
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                Operation::DigitalWrite { .. } => "digitalWrite",
+                Operation::AnalogWrite { .. } => "analogWrite",
+                Operation::Pulse { .. } => "pulse",
+                Operation::ServoAngle { .. } => "servoAngle",
+                Operation::ReadPin { .. } => "readPin",
+                Operation::Dispense { .. } => "dispense",
+            }
+        }
+
+        pub fn validate(&self) -> Result<(), Error> {
+            let pin = self.pin();
+            if !(MIN_PIN..=MAX_PIN).contains(&pin) {
+                return Err(invalid(format!(
+                    "pin {} is out of range {}-{}",
+                    pin, MIN_PIN, MAX_PIN
+                )));
+            }
+            match *self {
+                Operation::AnalogWrite { pin, .. } if !PWM_PINS.contains(&pin) => {
+                    Err(invalid(format!("pin {} does not support PWM", pin)))
+                }
+                Operation::Pulse { millis, .. } if millis == 0 || millis > MAX_PULSE_MILLIS => {
+                    Err(invalid(format!(
+                        "pulse of {}ms is out of range 1-{}",
+                        millis, MAX_PULSE_MILLIS
+                    )))
+                }
+                Operation::ServoAngle { angle, .. } if angle > MAX_SERVO_ANGLE => {
+                    Err(invalid(format!(
+                        "servo angle {} is out of range 0-{}",
+                        angle, MAX_SERVO_ANGLE
+                    )))
+                }
+                Operation::Dispense { amount: 0, .. } => {
+                    Err(invalid(String::from("dispense amount must be positive")))
+                }
+                Operation::Dispense {
+                    mosaic: Some(ref mosaic),
+                    ..
+                } => mosaic.validate(),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl Mosaic {
+        pub fn validate(&self) -> Result<(), Error> {
+            if self.id.len() != MOSAIC_ID_LENGTH || hex::decode(&self.id).is_err() {
+                return Err(invalid(format!(
+                    "mosaic id {} must be {} hex characters",
+                    self.id, MOSAIC_ID_LENGTH
+                )));
+            }
+            if self.divisibility > MAX_DIVISIBILITY {
+                return Err(invalid(format!(
+                    "divisibility {} is out of range 0-{}",
+                    self.divisibility, MAX_DIVISIBILITY
+                )));
+            }
+            Ok(())
+        }
+    }
+
     impl CommandResponse {
-        fn success(command: &Command) -> Self {
+        fn success(operation: Operation) -> Self {
             CommandResponse {
                 status: true,
-                pin: command.pin,
-                operation: command.operation.clone(),
+                pin: operation.pin(),
+                operation,
             }
         }
 
-        fn failure(command: &Command) -> Self {
+        fn failure(operation: Operation) -> Self {
             CommandResponse {
                 status: false,
-                pin: command.pin,
-                operation: command.operation.clone(),
+                pin: operation.pin(),
+                operation,
             }
         }
     }
 
     impl Command {
+        pub fn new(operation: &Operation) -> Result<Command, Error> {
+            operation.validate()?;
+            let (value, currency_amount, mosaic) = match *operation {
+                Operation::DigitalWrite { value, .. } => (value as u32, 0, None),
+                Operation::AnalogWrite { value, .. } => (u32::from(value), 0, None),
+                Operation::Pulse { millis, .. } => (millis, 0, None),
+                Operation::ServoAngle { angle, .. } => (u32::from(angle), 0, None),
+                Operation::ReadPin { .. } => (0, 0, None),
+                Operation::Dispense {
+                    amount, ref mosaic, ..
+                } => (0, amount, mosaic.clone()),
+            };
+            Ok(Command {
+                version: COMMAND_VERSION,
+                command: operation.code(),
+                pin: operation.pin(),
+                value,
+                currency_amount,
+                mosaic_id: mosaic.as_ref().map(|mosaic| mosaic.id.clone()),
+                divisibility: mosaic.map(|mosaic| mosaic.divisibility),
+            })
+        }
+
+        fn legacy_version() -> u8 {
+            1
+        }
+
+        pub fn from_json(data: &str) -> Result<Command, Error> {
+            let command: Command = serde_json::from_str(data)?;
+            if command.version == 0 || command.version > COMMAND_VERSION {
+                return Err(invalid(format!(
+                    "unsupported command version {}",
+                    command.version
+                )));
+            }
+            Ok(command)
+        }
+
+        pub fn operation(&self) -> Result<Operation, Error> {
+            let pin = self.pin;
+            let narrow = |max: u32| {
+                if self.value > max {
+                    Err(invalid(format!(
+                        "value {} is out of range 0-{}",
+                        self.value, max
+                    )))
+                } else {
+                    Ok(self.value as u8)
+                }
+            };
+            let operation = match self.command {
+                CommandCode::DigitalWrite => Operation::DigitalWrite {
+                    pin,
+                    value: narrow(1)? == 1,
+                },
+                CommandCode::AnalogWrite => Operation::AnalogWrite {
+                    pin,
+                    value: narrow(255)?,
+                },
+                CommandCode::Pulse => Operation::Pulse {
+                    pin,
+                    millis: self.value,
+                },
+                CommandCode::ServoAngle => Operation::ServoAngle {
+                    pin,
+                    angle: narrow(255)?,
+                },
+                CommandCode::ReadPin => Operation::ReadPin { pin },
+                CommandCode::Dispense => Operation::Dispense {
+                    pin,
+                    amount: self.currency_amount,
+                    mosaic: self.mosaic_id.as_ref().map(|id| Mosaic {
+                        id: id.clone(),
+                        divisibility: self.divisibility.unwrap_or(0),
+                    }),
+                },
+            };
+            operation.validate()?;
+            Ok(operation)
+        }
+
         pub fn send_command(
             path: String,
             settings: SerialPortSettings,
-            command: i8,
-            pin: i8,
-            currency_amount: i8,
-            operation: String,
+            operation: Operation,
         ) -> Result<CommandResponse, Error> {
+            let command = Command::new(&operation)?;
             let mut port = SerialData::open_port(settings, &path)?;
+            Self::send_command_port(port.borrow_mut(), &command)
+        }
 
-            let command = Command {
-                operation: operation,
-                command: command,
-                currency_amount: currency_amount,
-                pin: pin,
-            };
-
+        pub fn send_command_port(
+            port: &mut Box<dyn SerialPort>,
+            command: &Command,
+        ) -> Result<CommandResponse, Error> {
+            let operation = command.operation()?;
             match Handshake::send::<Command>(
-                port.borrow_mut(),
-                &command,
+                port,
+                command,
                 COMAMND_PREFIX_BYTE,
                 AxonMessageType::CommandMessage,
             ) {
                 Ok(response) => match response {
-                    AxonMessageStatus::Success => Ok(CommandResponse::success(&command)),
-                    AxonMessageStatus::Failure => Ok(CommandResponse::failure(&command)),
+                    AxonMessageStatus::Success => Ok(CommandResponse::success(operation)),
+                    AxonMessageStatus::Failure => Ok(CommandResponse::failure(operation)),
                 },
                 Err(e) => Err(e),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::command::command::{Command, Mosaic, Operation};
+
+    #[test]
+    fn command_round_trip() {
+        let operations = vec![
+            Operation::DigitalWrite {
+                pin: 13,
+                value: true,
+            },
+            Operation::AnalogWrite { pin: 9, value: 128 },
+            Operation::Pulse {
+                pin: 7,
+                millis: 250,
+            },
+            Operation::ServoAngle { pin: 4, angle: 90 },
+            Operation::ReadPin { pin: 14 },
+            Operation::Dispense {
+                pin: 8,
+                amount: 5,
+                mosaic: None,
+            },
+            Operation::Dispense {
+                pin: 8,
+                amount: 10_000_000_000,
+                mosaic: Some(Mosaic {
+                    id: String::from("6BED913FA20223F8"),
+                    divisibility: 6,
+                }),
+            },
+        ];
+        for operation in operations {
+            let command = Command::new(&operation).unwrap();
+            let json = serde_json::to_string(&command).unwrap();
+            let parsed = Command::from_json(&json).unwrap();
+            assert_eq!(parsed.operation().unwrap(), operation);
+        }
+    }
+
+    #[test]
+    fn wire_encoding() {
+        let command = Command::new(&Operation::DigitalWrite {
+            pin: 13,
+            value: true,
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&command).unwrap(),
+            "{\"version\":2,\"command\":68,\"pin\":13,\"value\":1,\"currency_amount\":0}"
+        );
+    }
+
+    #[test]
+    fn command_versions() {
+        let legacy = "{\"command\":77,\"pin\":8,\"value\":0,\"currency_amount\":5}";
+        assert!(Command::from_json(legacy).unwrap().operation().is_ok());
+        let negative = "{\"command\":77,\"pin\":8,\"value\":0,\"currency_amount\":-5}";
+        assert!(Command::from_json(negative).is_err());
+        let future = "{\"version\":9,\"command\":77,\"pin\":8,\"value\":0,\"currency_amount\":5}";
+        assert!(Command::from_json(future).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_operations() {
+        let invalid = vec![
+            Operation::DigitalWrite {
+                pin: 1,
+                value: true,
+            },
+            Operation::ReadPin { pin: 20 },
+            Operation::AnalogWrite { pin: 4, value: 10 },
+            Operation::Pulse { pin: 7, millis: 0 },
+            Operation::ServoAngle { pin: 4, angle: 181 },
+            Operation::Dispense {
+                pin: 8,
+                amount: 0,
+                mosaic: None,
+            },
+            Operation::Dispense {
+                pin: 8,
+                amount: 5,
+                mosaic: Some(Mosaic {
+                    id: String::from("not-a-mosaic"),
+                    divisibility: 0,
+                }),
+            },
+        ];
+        for operation in invalid {
+            assert!(Command::new(&operation).is_err());
+        }
+    }
+}
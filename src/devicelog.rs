@@ -0,0 +1,294 @@
+pub mod device_log {
+
+    use serde::{Deserialize, Serialize};
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::prelude::*;
+    use std::io::Result as SingleResult;
+    use std::path::Path;
+    use std::sync::{Condvar, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub const LOG_CAPACITY: usize = 512;
+
+    static SINK: Mutex<LogSink> = Mutex::new(LogSink {
+        ring: LogRing::new(LOG_CAPACITY),
+        file: None,
+    });
+    static NEW_LOGS: Condvar = Condvar::new();
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+    #[serde(rename_all = "lowercase")]
+    pub enum LogLevel {
+        Trace,
+        Debug,
+        Info,
+        Warn,
+        Error,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DeviceLog {
+        pub id: u64,
+        pub level: LogLevel,
+        // milliseconds since the device booted, when the firmware prints it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timestamp: Option<u64>,
+        pub received_at: u64,
+        pub port: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub source: Option<String>,
+        pub text: String,
+    }
+
+    pub struct LogRing {
+        capacity: usize,
+        next_id: u64,
+        entries: VecDeque<DeviceLog>,
+    }
+
+    pub struct RotatingFile {
+        path: String,
+        max_bytes: u64,
+        max_files: u32,
+    }
+
+    struct LogSink {
+        ring: LogRing,
+        file: Option<RotatingFile>,
+    }
+
+    impl LogLevel {
+        pub fn parse(token: &str) -> Option<LogLevel> {
+            match token.to_ascii_lowercase().as_str() {
+                "t" | "trace" => Some(LogLevel::Trace),
+                "d" | "debug" => Some(LogLevel::Debug),
+                "i" | "info" => Some(LogLevel::Info),
+                "w" | "warn" | "warning" => Some(LogLevel::Warn),
+                "e" | "err" | "error" => Some(LogLevel::Error),
+                _ => None,
+            }
+        }
+    }
+
+    impl DeviceLog {
+        // firmware lines look like `[WARN] 10233 pump: flow is low`, where the
+        // level, uptime and source are all optional. anything that doesn't fit is
+        // kept whole as an info line.
+        pub fn parse(port: &str, line: &str) -> DeviceLog {
+            let mut rest = line.trim();
+            let mut level = LogLevel::Info;
+            let bracketed = rest
+                .strip_prefix('[')
+                .and_then(|tail| tail.find(']').map(|end| (&tail[..end], &tail[end + 1..])));
+            if let Some((token, tail)) = bracketed {
+                if let Some(parsed) = LogLevel::parse(token) {
+                    level = parsed;
+                    rest = tail.trim_start();
+                }
+            } else if let Some((token, tail)) = rest.split_once(':') {
+                if let Some(parsed) = LogLevel::parse(token) {
+                    level = parsed;
+                    rest = tail.trim_start();
+                }
+            }
+
+            let mut timestamp = None;
+            if let Some((token, tail)) = rest.split_once(' ') {
+                if let Ok(millis) = token.parse::<u64>() {
+                    timestamp = Some(millis);
+                    rest = tail.trim_start();
+                }
+            }
+
+            let mut source = None;
+            if let Some((token, tail)) = rest.split_once(": ") {
+                let is_source = !token.is_empty()
+                    && token
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+                if is_source {
+                    source = Some(token.to_string());
+                    rest = tail;
+                }
+            }
+
+            DeviceLog {
+                id: 0,
+                level,
+                timestamp,
+                received_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_millis() as u64)
+                    .unwrap_or_default(),
+                port: port.to_string(),
+                source,
+                text: rest.to_string(),
+            }
+        }
+    }
+
+    impl LogRing {
+        pub const fn new(capacity: usize) -> LogRing {
+            LogRing {
+                capacity,
+                next_id: 1,
+                entries: VecDeque::new(),
+            }
+        }
+
+        // ids start at 1, so a cursor of 0 reads everything still buffered.
+        pub fn push(&mut self, mut log: DeviceLog) -> DeviceLog {
+            log.id = self.next_id;
+            self.next_id += 1;
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(log.clone());
+            log
+        }
+
+        pub fn since(&self, cursor: u64) -> Vec<DeviceLog> {
+            self.entries
+                .iter()
+                .filter(|log| log.id > cursor)
+                .cloned()
+                .collect()
+        }
+    }
+
+    impl RotatingFile {
+        pub fn new(path: String, max_bytes: u64, max_files: u32) -> RotatingFile {
+            RotatingFile {
+                path,
+                max_bytes,
+                max_files,
+            }
+        }
+
+        fn rotated(&self, index: u32) -> String {
+            format!("{}.{}", self.path, index)
+        }
+
+        // `path` is always the newest file, `path.1` the one before it, and so on
+        // up to `max_files`.
+        fn rotate(&self) -> SingleResult<()> {
+            if self.max_files == 0 {
+                return fs::remove_file(&self.path);
+            }
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if Path::new(&from).exists() {
+                    fs::rename(&from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))
+        }
+
+        pub fn write(&self, log: &DeviceLog) -> SingleResult<()> {
+            let size = fs::metadata(&self.path).map(|meta| meta.len()).unwrap_or(0);
+            if size >= self.max_bytes {
+                self.rotate()?;
+            }
+            let mut line = serde_json::to_string(log)?;
+            line.push('\n');
+            let mut log_file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            log_file.write_all(line.as_bytes())
+        }
+    }
+
+    pub struct DeviceLogs;
+
+    impl DeviceLogs {
+        pub fn record(port: &str, line: &str) -> DeviceLog {
+            let mut sink = SINK.lock().unwrap();
+            let log = sink.ring.push(DeviceLog::parse(port, line));
+            if let Some(file) = sink.file.as_ref() {
+                // a full disk shouldn't take the serial link down with it.
+                let _ = file.write(&log);
+            }
+            NEW_LOGS.notify_all();
+            log
+        }
+
+        pub fn set_file(file: Option<RotatingFile>) {
+            SINK.lock().unwrap().file = file;
+        }
+
+        pub fn since(cursor: u64) -> Vec<DeviceLog> {
+            SINK.lock().unwrap().ring.since(cursor)
+        }
+
+        // blocks until a log newer than `cursor` arrives or `timeout` passes.
+        pub fn wait(cursor: u64, timeout: Duration) -> Vec<DeviceLog> {
+            let sink = SINK.lock().unwrap();
+            let (sink, _) = NEW_LOGS
+                .wait_timeout_while(sink, timeout, |sink| sink.ring.next_id <= cursor + 1)
+                .unwrap();
+            sink.ring.since(cursor)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::devicelog::device_log::{DeviceLog, LogLevel, LogRing, RotatingFile};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn parse_device_logs() {
+        let log = DeviceLog::parse("/dev/ttyACM0", "[WARN] 10233 pump: flow is low");
+        assert_eq!(log.level, LogLevel::Warn);
+        assert_eq!(log.timestamp, Some(10233));
+        assert_eq!(log.source.as_deref(), Some("pump"));
+        assert_eq!(log.text, "flow is low");
+
+        let log = DeviceLog::parse("/dev/ttyACM0", "error: eeprom write failed");
+        assert_eq!(log.level, LogLevel::Error);
+        assert_eq!(log.source, None);
+        assert_eq!(log.text, "eeprom write failed");
+
+        let log = DeviceLog::parse("/dev/ttyACM0", "booting sensor v2.1");
+        assert_eq!(log.level, LogLevel::Info);
+        assert_eq!(log.timestamp, None);
+        assert_eq!(log.text, "booting sensor v2.1");
+    }
+
+    #[test]
+    fn ring_and_rotation() {
+        let mut ring = LogRing::new(2);
+        for line in &["one", "two", "three"] {
+            ring.push(DeviceLog::parse("/dev/test-log", line));
+        }
+        let kept: Vec<String> = ring.since(0).into_iter().map(|log| log.text).collect();
+        assert_eq!(kept, vec!["two", "three"]);
+        assert_eq!(ring.since(2).len(), 1);
+
+        let mut dir = env::temp_dir();
+        dir.push("axon-device-log-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = format!("{}/device.log", dir.to_str().unwrap());
+        let file = RotatingFile::new(path.clone(), 1, 2);
+        for log in ring.since(0) {
+            file.write(&log).unwrap();
+        }
+        file.write(&ring.since(0)[0]).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("\"text\":\"two\""));
+        assert!(fs::read_to_string(format!("{}.1", path))
+            .unwrap()
+            .contains("\"text\":\"three\""));
+        assert!(fs::read_to_string(format!("{}.2", path))
+            .unwrap()
+            .contains("\"text\":\"two\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
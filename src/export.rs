@@ -0,0 +1,272 @@
+pub mod record_export {
+
+    use crate::history::record_history::{RecordQuery, RecordStore};
+    use crate::record::record::{Reading, Record};
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::io::BufWriter;
+    use std::io::Result as SingleResult;
+    use std::io::{Error, ErrorKind};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Column {
+        ReceivedAt,
+        DeviceId,
+        SensorName,
+        RecordType,
+        Data,
+        Sensor,
+        Value,
+        Unit,
+        Timestamp,
+        WallTime,
+        Quality,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ExportFormat {
+        Csv(Vec<Column>),
+        Ndjson,
+    }
+
+    impl Column {
+        pub const ALL: [Column; 11] = [
+            Column::ReceivedAt,
+            Column::DeviceId,
+            Column::SensorName,
+            Column::RecordType,
+            Column::Data,
+            Column::Sensor,
+            Column::Value,
+            Column::Unit,
+            Column::Timestamp,
+            Column::WallTime,
+            Column::Quality,
+        ];
+
+        pub const DEFAULT: [Column; 6] = [
+            Column::ReceivedAt,
+            Column::DeviceId,
+            Column::Sensor,
+            Column::Value,
+            Column::Unit,
+            Column::Quality,
+        ];
+
+        pub fn name(&self) -> &'static str {
+            match *self {
+                Column::ReceivedAt => "receivedAt",
+                Column::DeviceId => "deviceId",
+                Column::SensorName => "sensorName",
+                Column::RecordType => "recordType",
+                Column::Data => "data",
+                Column::Sensor => "sensor",
+                Column::Value => "value",
+                Column::Unit => "unit",
+                Column::Timestamp => "timestamp",
+                Column::WallTime => "wallTime",
+                Column::Quality => "quality",
+            }
+        }
+
+        pub fn parse(name: &str) -> Option<Column> {
+            Self::ALL
+                .iter()
+                .find(|column| column.name() == name)
+                .cloned()
+        }
+
+        // a comma separated list such as "receivedAt,sensor,value".
+        pub fn parse_list(names: &str) -> SingleResult<Vec<Column>> {
+            names
+                .split(',')
+                .map(|name| {
+                    Self::parse(name.trim()).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown column `{}`", name.trim()),
+                        )
+                    })
+                })
+                .collect()
+        }
+
+        fn value(&self, record: &Record, reading: Option<&Reading>) -> String {
+            let optional = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+            match (*self, reading) {
+                (Column::ReceivedAt, _) => optional(record.received_at()),
+                (Column::DeviceId, _) => record.device_id().to_string(),
+                (Column::SensorName, _) => record.sensor_name().to_string(),
+                (Column::RecordType, _) => (record.record_type() as i8).to_string(),
+                (Column::Data, _) => record.data().to_string(),
+                (Column::Sensor, Some(reading)) => reading.sensor.clone(),
+                (Column::Value, Some(reading)) => reading.value.to_string(),
+                (Column::Unit, Some(reading)) => reading.unit.clone(),
+                (Column::Timestamp, Some(reading)) => optional(reading.timestamp),
+                (Column::WallTime, Some(reading)) => optional(reading.wall_time),
+                (Column::Quality, Some(reading)) => reading.quality.name().to_string(),
+                (_, None) => String::new(),
+            }
+        }
+    }
+
+    fn csv_field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
+    fn csv_row<W: Write>(out: &mut W, fields: Vec<String>) -> SingleResult<()> {
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        writeln!(out, "{}", row.join(","))
+    }
+
+    pub struct RecordExport;
+
+    impl RecordExport {
+        // CSV has one row per reading, records without readings get a single
+        // row with the reading columns left empty. NDJSON has one record per
+        // line. returns the number of rows written.
+        pub fn write<W: Write>(
+            store: &RecordStore,
+            query: &RecordQuery,
+            format: &ExportFormat,
+            out: &mut W,
+        ) -> SingleResult<u64> {
+            let mut rows = 0;
+            match format {
+                ExportFormat::Csv(columns) => {
+                    if columns.is_empty() {
+                        return Err(Error::new(ErrorKind::InvalidInput, "no columns to export"));
+                    }
+                    csv_row(out, columns.iter().map(|c| c.name().to_string()).collect())?;
+                    store.scan(query, |record| {
+                        let readings: Vec<Reading> = record
+                            .readings()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter(|reading| {
+                                query
+                                    .sensor
+                                    .as_ref()
+                                    .is_none_or(|sensor| reading.sensor == *sensor)
+                            })
+                            .collect();
+                        if readings.is_empty() {
+                            rows += 1;
+                            return csv_row(
+                                out,
+                                columns.iter().map(|c| c.value(&record, None)).collect(),
+                            );
+                        }
+                        for reading in readings.iter() {
+                            rows += 1;
+                            csv_row(
+                                out,
+                                columns
+                                    .iter()
+                                    .map(|c| c.value(&record, Some(reading)))
+                                    .collect(),
+                            )?;
+                        }
+                        Ok(())
+                    })?;
+                }
+                ExportFormat::Ndjson => {
+                    store.scan(query, |record| {
+                        rows += 1;
+                        writeln!(out, "{}", serde_json::to_string(&record)?)
+                    })?;
+                }
+            }
+            out.flush()?;
+            Ok(rows)
+        }
+
+        pub fn to_file(
+            store: &RecordStore,
+            query: &RecordQuery,
+            format: &ExportFormat,
+            path: &str,
+        ) -> SingleResult<u64> {
+            let mut out = BufWriter::new(File::create(path)?);
+            let rows = Self::write(store, query, format, &mut out)?;
+            debug!(target: "axon::record", "exported {} rows to {}", rows, path);
+            Ok(rows)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::record_export::{Column, ExportFormat, RecordExport};
+    use crate::history::record_history::{RecordQuery, RecordStore};
+    use crate::record::fixture::{record, record_json};
+    use crate::record::record::Record;
+    use serde_json::json;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn csv_and_ndjson() {
+        let mut dir = env::temp_dir();
+        dir.push("axon-record-export-test");
+        let _ = fs::remove_dir_all(&dir);
+        let store = RecordStore::new(dir.to_str().unwrap().to_string(), 30, u64::MAX);
+        let mut structured = record_json("tank", "", "");
+        structured["recordType"] = json!(78);
+        structured["readings"] = json!([
+            { "sensor": "temperature", "value": 21.5, "unit": "C" },
+            { "sensor": "level", "value": 0.5, "unit": "m", "quality": "bad" }
+        ]);
+        let mut structured: Record = serde_json::from_value(structured).unwrap();
+        structured.stamp("/dev/test-export", 1_700_000_000_000);
+        store.append(&structured).unwrap();
+        let mut text = record("tank", "lid", "lid open, \"twice\"");
+        text.stamp("/dev/test-export", 1_700_000_000_001);
+        store.append(&text).unwrap();
+
+        let columns = Column::parse_list("receivedAt, sensor,value,quality,data").unwrap();
+        assert!(Column::parse_list("receivedAt,colour").is_err());
+        let mut csv = Vec::new();
+        let rows = RecordExport::write(
+            &store,
+            &RecordQuery::default(),
+            &ExportFormat::Csv(columns.clone()),
+            &mut csv,
+        )
+        .unwrap();
+        assert_eq!(rows, 3);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "receivedAt,sensor,value,quality,data\n\
+             1700000000000,temperature,21.5,good,\n\
+             1700000000000,level,0.5,bad,\n\
+             1700000000001,,,,\"lid open, \"\"twice\"\"\"\n"
+        );
+
+        let level = RecordQuery {
+            sensor: Some(String::from("level")),
+            ..RecordQuery::default()
+        };
+        let mut csv = Vec::new();
+        RecordExport::write(&store, &level, &ExportFormat::Csv(columns), &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 2);
+
+        let path = dir.join("export.ndjson");
+        let rows = RecordExport::to_file(
+            &store,
+            &RecordQuery::default(),
+            &ExportFormat::Ndjson,
+            path.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rows, 2);
+        let exported = fs::read_to_string(&path).unwrap();
+        let first: Record = serde_json::from_str(exported.lines().next().unwrap()).unwrap();
+        assert_eq!(first.received_at(), Some(1_700_000_000_000));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
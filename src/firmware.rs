@@ -0,0 +1,560 @@
+pub mod firmware_header {
+
+    use crate::axonmessage::axonmessage::BUILTIN_MESSAGE_TYPES;
+    use crate::capability::capability::{
+        Capabilities, FramingMode, MAX_FRAME_SIZE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    };
+    use crate::codec::codec::{Codec, BINARY_MARKER};
+    use crate::command::command::{
+        CommandCode, COMAMND_PREFIX_BYTE, MAX_PIN, MAX_PULSE_MILLIS, MAX_SERVO_ANGLE, MIN_PIN,
+        PWM_PINS,
+    };
+    use crate::handshake::handshake::{AxonHandshakeType, AxonMessageType, HANDSHAKE_PREFIX};
+    use crate::query::query::QUERY_PREFIX_BYTE;
+    use crate::record::record::RecordType;
+    use crate::registry::message_registry::MIN_CUSTOM_ID;
+    use crate::state::device_state::STATE_PREFIX_BYTE;
+    use crate::timesync::time_sync::TIME_SYNC_PREFIX_BYTE;
+    use std::fmt::Debug;
+    use std::fmt::Write as FmtWrite;
+    use std::fs;
+    use std::io::Error;
+
+    pub const HEADER_NAME: &str = "axon_protocol.h";
+
+    // plain C so the same header works in sketches and C++ firmware. frames
+    // from the device carry no prefix, frames from the host start with one.
+    const FRAME_HELPERS: &str = r#"struct axon_frame {
+  char prefix;
+  const char *type;
+  size_t type_len;
+  const char *payload;
+  size_t payload_len;
+  uint32_t seq;
+  int has_seq;
+};
+
+/* returns a pointer just past the closing quote of the string at `p`. */
+static inline const char *axon_skip_string(const char *p, const char *end) {
+  for (p++; p < end; p++) {
+    if (*p == '\\') {
+      p++;
+    } else if (*p == '"') {
+      return p + 1;
+    }
+  }
+  return NULL;
+}
+
+static inline const char *axon_skip_space(const char *p, const char *end) {
+  while (p < end && (*p == ' ' || *p == '\t' || *p == '\r' || *p == '\n')) {
+    p++;
+  }
+  return p;
+}
+
+/* returns a pointer just past the JSON value starting at `p`. */
+static inline const char *axon_skip_value(const char *p, const char *end) {
+  int depth = 0;
+  if (p >= end) {
+    return NULL;
+  }
+  if (*p == '"') {
+    return axon_skip_string(p, end);
+  }
+  while (p < end) {
+    if (*p == '"') {
+      p = axon_skip_string(p, end);
+      if (p == NULL) {
+        return NULL;
+      }
+      continue;
+    }
+    if (*p == '{' || *p == '[') {
+      depth++;
+    } else if (*p == '}' || *p == ']') {
+      if (depth == 0) {
+        return p;
+      }
+      if (--depth == 0) {
+        return p + 1;
+      }
+    } else if (depth == 0 && (*p == ',' || *p == ' ')) {
+      return p;
+    }
+    p++;
+  }
+  return depth == 0 ? p : NULL;
+}
+
+/* finds `key` among the members of the object in `json` and points `value`
+   at its raw JSON. strings keep their quotes. returns 0 when found. */
+static inline int axon_member(const char *json, size_t len, const char *key,
+                              const char **value, size_t *value_len) {
+  const char *end = json + len;
+  const char *p = axon_skip_space(json, end);
+  size_t key_len = strlen(key);
+  if (p >= end || *p != '{') {
+    return -1;
+  }
+  p++;
+  while (p < end) {
+    const char *name;
+    const char *name_end;
+    p = axon_skip_space(p, end);
+    if (p >= end || *p != '"') {
+      return -1;
+    }
+    name = p + 1;
+    name_end = axon_skip_string(p, end);
+    if (name_end == NULL) {
+      return -1;
+    }
+    p = axon_skip_space(name_end, end);
+    if (p >= end || *p != ':') {
+      return -1;
+    }
+    p = axon_skip_space(p + 1, end);
+    if ((size_t)(name_end - name - 1) == key_len && memcmp(name, key, key_len) == 0) {
+      const char *value_end = axon_skip_value(p, end);
+      if (value_end == NULL) {
+        return -1;
+      }
+      *value = p;
+      *value_len = (size_t)(value_end - p);
+      return 0;
+    }
+    p = axon_skip_value(p, end);
+    if (p == NULL) {
+      return -1;
+    }
+    p = axon_skip_space(p, end);
+    if (p >= end || *p != ',') {
+      return -1;
+    }
+    p++;
+  }
+  return -1;
+}
+
+static inline int axon_member_u32(const char *json, size_t len, const char *key,
+                                  uint32_t *out) {
+  const char *value;
+  size_t value_len;
+  uint32_t number = 0;
+  size_t i;
+  if (axon_member(json, len, key, &value, &value_len) != 0 || value_len == 0) {
+    return -1;
+  }
+  for (i = 0; i < value_len; i++) {
+    if (value[i] < '0' || value[i] > '9') {
+      return -1;
+    }
+    number = number * 10 + (uint32_t)(value[i] - '0');
+  }
+  *out = number;
+  return 0;
+}
+
+/* points `out` at the characters of a string member, without quotes or
+   unescaping. */
+static inline int axon_member_string(const char *json, size_t len, const char *key,
+                                     const char **out, size_t *out_len) {
+  const char *value;
+  size_t value_len;
+  if (axon_member(json, len, key, &value, &value_len) != 0 || value_len < 2 ||
+      value[0] != '"') {
+    return -1;
+  }
+  *out = value + 1;
+  *out_len = value_len - 2;
+  return 0;
+}
+
+/* splits a line read from serial into its prefix and envelope. the frame
+   points into `line`, nothing is copied. returns 0 on success. */
+static inline int axon_parse_frame(const char *line, size_t len, struct axon_frame *frame) {
+  const char *meta;
+  size_t meta_len;
+  while (len > 0 && (line[len - 1] == '\n' || line[len - 1] == '\r')) {
+    len--;
+  }
+  frame->prefix = 0;
+  if (len > 0 && line[0] != '{') {
+    frame->prefix = line[0];
+    line++;
+    len--;
+  }
+  if (axon_member_string(line, len, "type", &frame->type, &frame->type_len) != 0) {
+    return -1;
+  }
+  if (axon_member(line, len, "payload", &frame->payload, &frame->payload_len) != 0) {
+    frame->payload = "null";
+    frame->payload_len = 4;
+  }
+  frame->seq = 0;
+  frame->has_seq = 0;
+  if (axon_member(line, len, "meta", &meta, &meta_len) == 0 &&
+      axon_member_u32(meta, meta_len, "seq", &frame->seq) == 0) {
+    frame->has_seq = 1;
+  }
+  return 0;
+}
+
+static inline int axon_frame_is(const struct axon_frame *frame, const char *type) {
+  return strlen(type) == frame->type_len && memcmp(frame->type, type, frame->type_len) == 0;
+}
+
+/* writes an envelope around `payload`, which must already be JSON. pass NULL
+   for `signature` on unsigned frames. returns the length written, or -1 when
+   the frame doesn't fit in `size` or AXON_MAX_FRAME_SIZE. the caller ends the
+   line, e.g. with Serial.println. */
+static inline int axon_build_frame(char *out, size_t size, const char *type,
+                                   const char *payload, uint32_t seq,
+                                   const char *signature) {
+  int written;
+  if (signature != NULL) {
+    written = snprintf(out, size,
+                       "{\"type\":\"%s\",\"payload\":%s,\"meta\":{\"seq\":%lu,\"signature\":\"%s\"}}",
+                       type, payload, (unsigned long)seq, signature);
+  } else {
+    written = snprintf(out, size, "{\"type\":\"%s\",\"payload\":%s,\"meta\":{\"seq\":%lu}}",
+                       type, payload, (unsigned long)seq);
+  }
+  if (written < 0 || (size_t)written >= size || written > AXON_MAX_FRAME_SIZE) {
+    return -1;
+  }
+  return written;
+}
+
+/* the connect a device sends before a record or any other device initiated
+   message. */
+static inline int axon_build_connect(char *out, size_t size, int message_type,
+                                     const char *device_id, uint32_t seq) {
+  char payload[384];
+  int written = snprintf(payload, sizeof(payload),
+                         "{\"handshakeType\":%d,\"messageType\":%d,\"version\":%d,\"capabilities\":%s,\"deviceId\":\"%s\"}",
+                         AXON_HANDSHAKE_CONNECT, message_type, AXON_PROTOCOL_VERSION,
+                         AXON_CAPABILITIES, device_id);
+  if (written < 0 || (size_t)written >= sizeof(payload)) {
+    return -1;
+  }
+  return axon_build_frame(out, size, AXON_TYPE_HANDSHAKE_REQUEST, payload, seq, NULL);
+}
+
+/* answers a host connect. `signature` is the device's signature over the
+   host's challenge. */
+static inline int axon_build_accept(char *out, size_t size, const char *device_id,
+                                    const char *signature, uint32_t seq) {
+  char payload[512];
+  int written = snprintf(payload, sizeof(payload),
+                         "{\"handshakeType\":%d,\"version\":%d,\"capabilities\":%s,\"deviceId\":\"%s\",\"signature\":\"%s\"}",
+                         AXON_HANDSHAKE_ACCEPT, AXON_PROTOCOL_VERSION, AXON_CAPABILITIES,
+                         device_id, signature);
+  if (written < 0 || (size_t)written >= sizeof(payload)) {
+    return -1;
+  }
+  return axon_build_frame(out, size, AXON_TYPE_HANDSHAKE_RESPONSE, payload, seq, NULL);
+}
+"#;
+
+    pub struct FirmwareHeader;
+
+    impl FirmwareHeader {
+        // everything the firmware needs to speak the protocol, generated from the
+        // host's own definitions.
+        pub fn render() -> String {
+            let mut header = String::new();
+            let _ = writeln!(
+                header,
+                "/* generated by `axon firmware header`, do not edit. */"
+            );
+            let _ = writeln!(header, "#ifndef AXON_PROTOCOL_H");
+            let _ = writeln!(header, "#define AXON_PROTOCOL_H\n");
+            for include in &["stddef.h", "stdint.h", "stdio.h", "string.h"] {
+                let _ = writeln!(header, "#include <{}>", include);
+            }
+
+            let _ = writeln!(header);
+            define(&mut header, "AXON_PROTOCOL_VERSION", PROTOCOL_VERSION);
+            define(
+                &mut header,
+                "AXON_MIN_PROTOCOL_VERSION",
+                MIN_PROTOCOL_VERSION,
+            );
+            define(&mut header, "AXON_MAX_FRAME_SIZE", MAX_FRAME_SIZE);
+            define(&mut header, "AXON_MIN_CUSTOM_ID", MIN_CUSTOM_ID);
+            define(
+                &mut header,
+                "AXON_BINARY_MARKER",
+                format!("0x{:02x}", BINARY_MARKER),
+            );
+
+            // what the connect and accept helpers advertise. firmware that speaks
+            // more, e.g. a binary codec or a smaller frame limit, defines its own
+            // before including this header.
+            let _ = writeln!(header, "\n#ifndef AXON_CAPABILITIES");
+            define(
+                &mut header,
+                "AXON_CAPABILITIES",
+                string_literal(&serde_json::to_string(&Self::capabilities()).unwrap_or_default()),
+            );
+            let _ = writeln!(header, "#endif");
+
+            let _ = writeln!(header);
+            let prefixes = [
+                ("HANDSHAKE", HANDSHAKE_PREFIX),
+                ("COMMAND", COMAMND_PREFIX_BYTE),
+                ("QUERY", QUERY_PREFIX_BYTE),
+                ("STATE", STATE_PREFIX_BYTE),
+                ("TIME_SYNC", TIME_SYNC_PREFIX_BYTE),
+            ];
+            for (name, prefix) in prefixes.iter() {
+                define(
+                    &mut header,
+                    &format!("AXON_PREFIX_{}", name),
+                    format!("'{}'", prefix),
+                );
+            }
+
+            let _ = writeln!(header);
+            for message_type in BUILTIN_MESSAGE_TYPES.iter() {
+                define(
+                    &mut header,
+                    &format!("AXON_TYPE_{}", screaming_snake(message_type)),
+                    format!("\"{}\"", message_type),
+                );
+            }
+
+            let handshake_types: Vec<(String, i64)> = AxonHandshakeType::ALL
+                .iter()
+                .map(|kind| (variant(kind), *kind as i64))
+                .collect();
+            enumeration(
+                &mut header,
+                "axon_handshake_type",
+                "AXON_",
+                &handshake_types,
+            );
+
+            let message_types: Vec<(String, i64)> = (0..MIN_CUSTOM_ID)
+                .filter_map(AxonMessageType::from_id)
+                .map(|kind| (variant(&kind), kind.id() as i64))
+                .collect();
+            enumeration(&mut header, "axon_message_type", "AXON_", &message_types);
+
+            let record_types: Vec<(String, i64)> = RecordType::ALL
+                .iter()
+                .map(|kind| (variant(kind), *kind as i64))
+                .collect();
+            enumeration(
+                &mut header,
+                "axon_record_type",
+                "AXON_RECORD_",
+                &record_types,
+            );
+
+            let command_codes: Vec<(String, i64)> = CommandCode::ALL
+                .iter()
+                .map(|code| (variant(code), *code as i64))
+                .collect();
+            enumeration(
+                &mut header,
+                "axon_command_code",
+                "AXON_COMMAND_",
+                &command_codes,
+            );
+
+            let codecs: Vec<(String, i64)> = Codec::PREFERENCE
+                .iter()
+                .map(|codec| (variant(codec), i64::from(codec.id())))
+                .collect();
+            enumeration(&mut header, "axon_codec", "AXON_CODEC_", &codecs);
+
+            let _ = writeln!(header);
+            define(&mut header, "AXON_MIN_PIN", MIN_PIN);
+            define(&mut header, "AXON_MAX_PIN", MAX_PIN);
+            define(&mut header, "AXON_MAX_SERVO_ANGLE", MAX_SERVO_ANGLE);
+            define(
+                &mut header,
+                "AXON_MAX_PULSE_MILLIS",
+                format!("{}UL", MAX_PULSE_MILLIS),
+            );
+            let pins: Vec<String> = PWM_PINS.iter().map(u8::to_string).collect();
+            let _ = writeln!(
+                header,
+                "static const uint8_t AXON_PWM_PINS[{}] = {{{}}};",
+                PWM_PINS.len(),
+                pins.join(", ")
+            );
+
+            let _ = writeln!(header);
+            header.push_str(FRAME_HELPERS);
+            let _ = writeln!(header, "\n#endif /* AXON_PROTOCOL_H */");
+            header
+        }
+
+        // the helpers only build JSON envelopes at the current version, for the
+        // built in types and the capability handshake. an empty list would claim
+        // every registered type too.
+        pub fn capabilities() -> Capabilities {
+            let mut message_types = vec![AxonMessageType::CapabilityMessage];
+            for message_type in BUILTIN_MESSAGE_TYPES
+                .iter()
+                .filter_map(|message_type| AxonMessageType::from_envelope_type(message_type))
+            {
+                if !message_types.contains(&message_type) {
+                    message_types.push(message_type);
+                }
+            }
+            message_types.sort_by_key(AxonMessageType::id);
+            Capabilities {
+                versions: vec![PROTOCOL_VERSION],
+                message_types,
+                framing: vec![FramingMode::Envelope],
+                max_frame_size: Some(MAX_FRAME_SIZE),
+                codecs: vec![Codec::Json],
+                ..Capabilities::default()
+            }
+        }
+
+        pub fn write(path: &str) -> Result<(), Error> {
+            fs::write(path, Self::render())
+        }
+    }
+
+    fn define<T: ToString>(header: &mut String, name: &str, value: T) {
+        let _ = writeln!(header, "#define {} {}", name, value.to_string());
+    }
+
+    fn string_literal(value: &str) -> String {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    fn enumeration(header: &mut String, name: &str, prefix: &str, variants: &[(String, i64)]) {
+        let _ = writeln!(header, "\nenum {} {{", name);
+        for (variant, value) in variants {
+            let _ = writeln!(header, "  {}{} = {},", prefix, variant, value);
+        }
+        let _ = writeln!(header, "}};");
+    }
+
+    // `HandshakeConnect` and `handshakeRequest` both become `HANDSHAKE_...`.
+    fn screaming_snake(name: &str) -> String {
+        let mut snake = String::new();
+        for (i, c) in name.chars().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_uppercase());
+        }
+        snake
+    }
+
+    fn variant<T: Debug>(value: &T) -> String {
+        screaming_snake(&format!("{:?}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axonmessage::axonmessage::Frame;
+    use crate::capability::capability::PROTOCOL_VERSION;
+    use crate::capture::capture::{CaptureEntry, Direction, ReplayPort};
+    use crate::codec::codec::Codec;
+    use crate::firmware::firmware_header::{FirmwareHeader, HEADER_NAME};
+    use crate::handshake::handshake::{
+        AxonMessageType, Handshake, HandshakeRequest, HandshakeResponse,
+    };
+    use serialport::prelude::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    const HELPER_PROGRAM: &str = r#"#include "axon_protocol.h"
+
+int main(void) {
+  char frame[AXON_MAX_FRAME_SIZE + 1];
+  if (axon_build_connect(frame, sizeof(frame), AXON_RECORD_MESSAGE, "tank", 1) < 0) {
+    return 1;
+  }
+  printf("%s\n", frame);
+  if (axon_build_accept(frame, sizeof(frame), "tank", "00", 2) < 0) {
+    return 1;
+  }
+  printf("%s\n", frame);
+  return 0;
+}
+"#;
+
+    // compiles the helpers and reads what they build back through the host.
+    fn helper_frames() -> Vec<Frame> {
+        let mut dir = env::temp_dir();
+        dir.push("axon-firmware-header-test");
+        fs::create_dir_all(&dir).unwrap();
+        FirmwareHeader::write(dir.join(HEADER_NAME).to_str().unwrap()).unwrap();
+        fs::write(dir.join("main.c"), HELPER_PROGRAM).unwrap();
+        let compiler = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+        let status = Command::new(compiler)
+            .current_dir(&dir)
+            .args(["-std=c99", "-Wall", "-Werror", "-o", "helpers", "main.c"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(dir.join("helpers")).output().unwrap();
+        assert!(output.status.success());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let entry = CaptureEntry {
+            at: 0,
+            port: String::from("firmware"),
+            direction: Direction::In,
+            data: hex::encode(&output.stdout),
+        };
+        let mut port: Box<dyn SerialPort> =
+            Box::new(ReplayPort::new(String::from("replay:firmware-helpers"), &[entry]).unwrap());
+        vec![
+            Handshake::read_frame(&mut port).unwrap(),
+            Handshake::read_frame(&mut port).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn header_matches_protocol() {
+        let header = FirmwareHeader::render();
+        assert!(header.contains("  AXON_HANDSHAKE_CONNECT = 18499,"));
+        assert!(header.contains("  AXON_RECORD_SIMPLE = 83,"));
+        assert!(header.contains("  AXON_PAIR_MESSAGE = 5,"));
+        assert!(header.contains("#define AXON_PREFIX_STATE 'S'"));
+        assert!(header.contains("#define AXON_TYPE_QUERY_RESPONSE \"queryResponse\""));
+
+        let mut path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("firmware");
+        path.push(HEADER_NAME);
+        assert!(
+            fs::read_to_string(&path).unwrap() == header,
+            "{} is out of date, regenerate it with `axon firmware header {}`",
+            HEADER_NAME,
+            path.display()
+        );
+
+        let mut frames = helper_frames().into_iter();
+        let connect = match frames.next() {
+            Some(Frame::Envelope(envelope)) => envelope.open::<HandshakeRequest>().unwrap(),
+            _ => panic!("connect is not an envelope"),
+        };
+        let negotiation = connect.negotiation().unwrap();
+        assert_eq!(negotiation.version, PROTOCOL_VERSION);
+        assert_eq!(negotiation.codec, Codec::Json);
+        assert_eq!(negotiation.device, FirmwareHeader::capabilities());
+        assert!(negotiation.check(&AxonMessageType::QueryMessage).is_ok());
+        assert!(negotiation.check(&AxonMessageType::PairMessage).is_err());
+        assert!(negotiation.check(&AxonMessageType::Custom(64)).is_err());
+        let accept = match frames.next() {
+            Some(Frame::Envelope(envelope)) => envelope.open::<HandshakeResponse>().unwrap(),
+            _ => panic!("accept is not an envelope"),
+        };
+        assert_eq!(accept.negotiation().unwrap(), negotiation);
+    }
+}
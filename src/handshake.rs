@@ -1,41 +1,190 @@
 pub mod handshake {
-    use crate::axonmessage::axonmessage::{AxonMessage, Message, Sendable};
+    use crate::axonmessage::axonmessage::{
+        AxonMessage, Envelope, Frame, Message, Metadata, Sendable,
+    };
+    use crate::capability::capability::{Capabilities, Negotiation, PROTOCOL_VERSION};
+    use crate::capture::capture::ReplayPort;
+    use crate::codec::codec::Codec;
+    use crate::devicelog::device_log::DeviceLogs;
+    use crate::logging::host_log::Span;
+    use crate::metrics::link_metrics::Metrics;
+    use crate::pairing::device_pairing::{PairedDevice, PairedDevices};
+    use crate::registry::message_registry::MIN_CUSTOM_ID;
+    use crate::schema::message_schema::{integer_enum, Schemas};
+    use crate::sequence::sequence::{Delivery, SequenceTracker};
     use crate::serial::serial_handler::SerialData;
-    use serde::de::DeserializeOwned;
-    use serde::{Deserialize, Serialize};
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::{
+        InstanceType, NumberValidation, Schema, SchemaObject, SubschemaValidation,
+    };
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use serde_repr::*;
     use serialport::prelude::*;
     use std::borrow::BorrowMut;
     use std::io::{Error, ErrorKind};
 
-    const HANDSHAKE_PREFIX: char = 'H';
+    pub const HANDSHAKE_PREFIX: char = 'H';
+    // frames a device may send after a connect before it has to have accepted.
+    const MAX_CONNECT_FRAMES: usize = 32;
 
-    #[derive(Serialize_repr, Deserialize_repr, Debug, PartialEq)]
+    #[derive(Serialize_repr, Deserialize_repr, Debug, PartialEq, Clone, Copy)]
     #[repr(i16)]
     pub enum AxonHandshakeType {
         HandshakeConnect = 18499,
         HandshakeAccept = 18497,
     }
 
-    #[derive(Serialize_repr, Deserialize_repr, PartialEq, Debug)]
-    #[repr(i8)]
+    impl AxonHandshakeType {
+        pub const ALL: [AxonHandshakeType; 2] = [
+            AxonHandshakeType::HandshakeConnect,
+            AxonHandshakeType::HandshakeAccept,
+        ];
+    }
+
+    impl JsonSchema for AxonHandshakeType {
+        fn schema_name() -> String {
+            String::from("AxonHandshakeType")
+        }
+
+        fn json_schema(_: &mut SchemaGenerator) -> Schema {
+            integer_enum(AxonHandshakeType::ALL.iter().map(|kind| *kind as i64))
+        }
+    }
+
+    // travels as a plain i8 on the wire, ids from `MIN_CUSTOM_ID` up belong to
+    // message types registered by the application.
+    #[derive(PartialEq, Debug, Clone, Copy)]
     pub enum AxonMessageType {
-        RecordMessage = 0,
-        StateMessage = 1,
-        CommandMessage = 2,
+        RecordMessage,
+        StateMessage,
+        CommandMessage,
+        QueryMessage,
+        CapabilityMessage,
+        PairMessage,
+        TimeSyncMessage,
+        Custom(i8),
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    impl AxonMessageType {
+        pub fn id(&self) -> i8 {
+            match *self {
+                AxonMessageType::RecordMessage => 0,
+                AxonMessageType::StateMessage => 1,
+                AxonMessageType::CommandMessage => 2,
+                AxonMessageType::QueryMessage => 3,
+                AxonMessageType::CapabilityMessage => 4,
+                AxonMessageType::PairMessage => 5,
+                AxonMessageType::TimeSyncMessage => 6,
+                AxonMessageType::Custom(id) => id,
+            }
+        }
+
+        pub fn from_id(id: i8) -> Option<AxonMessageType> {
+            match id {
+                0 => Some(AxonMessageType::RecordMessage),
+                1 => Some(AxonMessageType::StateMessage),
+                2 => Some(AxonMessageType::CommandMessage),
+                3 => Some(AxonMessageType::QueryMessage),
+                4 => Some(AxonMessageType::CapabilityMessage),
+                5 => Some(AxonMessageType::PairMessage),
+                6 => Some(AxonMessageType::TimeSyncMessage),
+                id if id >= MIN_CUSTOM_ID => Some(AxonMessageType::Custom(id)),
+                _ => None,
+            }
+        }
+
+        // the session a built in envelope type travels in, handshakes have none.
+        pub fn from_envelope_type(message_type: &str) -> Option<AxonMessageType> {
+            match message_type {
+                "record" => Some(AxonMessageType::RecordMessage),
+                "state" => Some(AxonMessageType::StateMessage),
+                "command" => Some(AxonMessageType::CommandMessage),
+                "query" | "queryResponse" => Some(AxonMessageType::QueryMessage),
+                "timeSync" | "timeSyncResponse" => Some(AxonMessageType::TimeSyncMessage),
+                _ => None,
+            }
+        }
+    }
+
+    impl Serialize for AxonMessageType {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_i8(self.id())
+        }
+    }
+
+    // a built in id, or any id registered for a custom message type.
+    impl JsonSchema for AxonMessageType {
+        fn schema_name() -> String {
+            String::from("AxonMessageType")
+        }
+
+        fn json_schema(_: &mut SchemaGenerator) -> Schema {
+            let custom = SchemaObject {
+                instance_type: Some(InstanceType::Integer.into()),
+                number: Some(Box::new(NumberValidation {
+                    minimum: Some(f64::from(MIN_CUSTOM_ID)),
+                    maximum: Some(f64::from(i8::MAX)),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            };
+            let builtin =
+                integer_enum((0..MIN_CUSTOM_ID).filter_map(|id| {
+                    AxonMessageType::from_id(id).map(|kind| i64::from(kind.id()))
+                }));
+            SchemaObject {
+                subschemas: Some(Box::new(SubschemaValidation {
+                    any_of: Some(vec![builtin, custom.into()]),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+            .into()
+        }
+    }
+
+    impl<'de> Deserialize<'de> for AxonMessageType {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let id = i8::deserialize(deserializer)?;
+            AxonMessageType::from_id(id)
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown message type id {}", id)))
+        }
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug)]
     #[serde(rename_all = "camelCase")]
     pub struct HandshakeRequest {
         handshake_type: AxonHandshakeType,
         message_type: AxonMessageType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capabilities: Option<Capabilities>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        challenge: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device_id: Option<String>,
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Serialize, Deserialize, JsonSchema)]
     #[serde(rename_all = "camelCase")]
     pub struct HandshakeResponse {
         handshake_type: AxonHandshakeType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        capabilities: Option<Capabilities>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        codec: Option<Codec>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        challenge: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        public_key: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
     }
 
     pub enum AxonMessageStatus {
@@ -46,64 +195,354 @@ pub mod handshake {
     #[derive(Serialize, Deserialize)]
     pub struct Handshake;
 
-    impl AxonMessage for HandshakeResponse {}
-    impl AxonMessage for HandshakeRequest {}
+    impl HandshakeRequest {
+        pub fn negotiation(&self) -> Result<Negotiation, Error> {
+            Negotiation::negotiate(self.capabilities.clone(), self.version)
+        }
+    }
+
+    impl HandshakeResponse {
+        pub fn negotiation(&self) -> Result<Negotiation, Error> {
+            Negotiation::negotiate(self.capabilities.clone(), self.version)?.with_codec(self.codec)
+        }
+    }
+
+    impl AxonMessage for HandshakeResponse {
+        fn message_type() -> &'static str {
+            "handshakeResponse"
+        }
+    }
+
+    impl AxonMessage for HandshakeRequest {
+        fn message_type() -> &'static str {
+            "handshakeRequest"
+        }
+    }
 
     impl Handshake {
-        fn check_type_from_str<T>(data: &str) -> bool
-        where
-            T: DeserializeOwned,
-        {
-            serde_json::from_str::<T>(&data).is_ok()
+        fn port_name(port: &dyn SerialPort) -> String {
+            port.name().unwrap_or_default()
+        }
+
+        pub fn write_frame<T: AxonMessage>(
+            port: &mut Box<dyn SerialPort>,
+            prefix_byte: char,
+            message: &T,
+        ) -> Result<bool, Error> {
+            Self::write_envelope(port, prefix_byte, Envelope::wrap(message, None)?)
+        }
+
+        pub fn write_envelope(
+            port: &mut Box<dyn SerialPort>,
+            prefix_byte: char,
+            mut envelope: Envelope,
+        ) -> Result<bool, Error> {
+            let port_name = Self::port_name(port.as_ref());
+            let seq = SequenceTracker::next_outbound(&port_name);
+            envelope.meta.get_or_insert_with(Metadata::default).seq = Some(seq);
+            // the handshake has to be readable before a codec is agreed on.
+            let handshake = envelope.is::<HandshakeRequest>() || envelope.is::<HandshakeResponse>();
+            let negotiation = if handshake {
+                None
+            } else {
+                Negotiation::load(&port_name)
+            };
+            let codec = negotiation
+                .as_ref()
+                .map(|negotiation| negotiation.codec)
+                .unwrap_or_default();
+            trace!(
+                target: "axon::frame",
+                "out {} seq {} as {:?} on {}",
+                envelope.message_type,
+                seq,
+                codec,
+                port_name
+            );
+            let mut frame = prefix_byte.to_string().into_bytes();
+            frame.extend(codec.frame(&envelope)?);
+            if let Some(negotiation) = negotiation {
+                if frame.len() > negotiation.max_frame_size() as usize {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "{} frame of {} bytes is over the device's limit of {}",
+                            envelope.message_type,
+                            frame.len(),
+                            negotiation.max_frame_size()
+                        ),
+                    ));
+                }
+            }
+            let written = SerialData::write_bytes(&frame, port.borrow_mut())?;
+            Metrics::frame_sent(&port_name, frame.len());
+            Ok(written)
+        }
+
+        // reads the next frame that isn't a repeat of one already seen on this port.
+        pub fn read_frame(port: &mut Box<dyn SerialPort>) -> Result<Frame, Error> {
+            let port_name = Self::port_name(port.as_ref());
+            loop {
+                let frame = SerialData::read_frame_bytes(port.borrow_mut())
+                    .and_then(|data| {
+                        let frame = Envelope::decode_bytes(&data)?;
+                        if let Frame::Envelope(ref envelope) = frame {
+                            Schemas::check(envelope)?;
+                        }
+                        let is_frame = matches!(frame, Frame::Envelope(_));
+                        Metrics::line_received(&port_name, data.len(), is_frame);
+                        Ok(frame)
+                    })
+                    .inspect_err(|e| Metrics::read_failed(&port_name, e))?;
+                match frame {
+                    Frame::Envelope(ref envelope) => {
+                        if SequenceTracker::accept_inbound(&port_name, envelope.seq())
+                            == Delivery::Duplicate
+                        {
+                            debug!(
+                                target: "axon::frame",
+                                "dropped duplicate {} seq {:?} on {}",
+                                envelope.message_type,
+                                envelope.seq(),
+                                port_name
+                            );
+                            continue;
+                        }
+                        trace!(
+                            target: "axon::frame",
+                            "in {} seq {:?} on {}",
+                            envelope.message_type,
+                            envelope.seq(),
+                            port_name
+                        );
+                    }
+                    Frame::Log(ref line) => {
+                        trace!(target: "axon::frame", "in device log on {}", port_name);
+                        DeviceLogs::record(&port_name, line);
+                    }
+                }
+                break Ok(frame);
+            }
+        }
+
+        // skips over device log lines until the next envelope arrives.
+        fn read_envelope(port: &mut Box<dyn SerialPort>) -> Result<Envelope, Error> {
+            loop {
+                if let Frame::Envelope(envelope) = Self::read_frame(port)? {
+                    break Ok(envelope);
+                }
+            }
+        }
+
+        // a replayed capture answers a challenge from the run it was recorded
+        // in, which no signature check can pass, so replays aren't checked.
+        fn paired_devices(port_name: &str) -> Result<PairedDevices, Error> {
+            if ReplayPort::is_replay(port_name) {
+                return Ok(PairedDevices::default());
+            }
+            PairedDevices::load(&String::from(crate::PAIRED_DEVICES_PATH))
         }
 
         pub fn recieve<T: AxonMessage>(
             port: &mut Box<dyn SerialPort>,
             message_type: AxonMessageType,
-        ) -> Result<Message<T>, Error>
-        where
-            T: serde::de::DeserializeOwned,
-        {
+        ) -> Result<Message<T>, Error> {
+            Ok(match Self::recieve_envelope(port, message_type)? {
+                Message::AxonMessage(envelope) => Message::AxonMessage(envelope.open::<T>()?),
+                Message::Empty => Message::Empty,
+                Message::Log { status, data } => Message::Log { status, data },
+            })
+        }
+
+        pub fn recieve_envelope(
+            port: &mut Box<dyn SerialPort>,
+            message_type: AxonMessageType,
+        ) -> Result<Message<Envelope>, Error> {
+            let _span = Span::enter("handshake", &Self::port_name(port.as_ref()));
+            let request = match Self::read_frame(port)? {
+                Frame::Log(data) => return Ok(Message::Log { status: 0, data }),
+                Frame::Envelope(envelope) => envelope.open::<HandshakeRequest>()?,
+            };
+            match request.handshake_type {
+                AxonHandshakeType::HandshakeConnect => {
+                    if request.message_type != message_type {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "expected a {:?} handshake, got {:?}",
+                                message_type, request.message_type
+                            ),
+                        ));
+                    }
+                    let port_name = Self::port_name(port.as_ref());
+                    let envelope =
+                        Metrics::observe_handshake(&port_name, || Self::accept(port, request))?;
+                    Ok(Message::AxonMessage(envelope))
+                }
+                _ => {
+                    debug!(
+                        target: "axon::handshake",
+                        "ignoring {:?} without a connect",
+                        request.handshake_type
+                    );
+                    Ok(Message::Empty)
+                }
+            }
+        }
+
+        // answers a device initiated connect, then reads the payload that follows.
+        fn accept(
+            port: &mut Box<dyn SerialPort>,
+            request: HandshakeRequest,
+        ) -> Result<Envelope, Error> {
+            debug!(target: "axon::handshake", "device connected for {:?}", request.message_type);
+            let negotiation = request.negotiation()?;
+            negotiation.check(&request.message_type)?;
+            Negotiation::store(&Self::port_name(port.as_ref()), &negotiation);
+            let challenge = PairedDevices::challenge()?;
             let accept = HandshakeResponse {
                 handshake_type: AxonHandshakeType::HandshakeAccept,
+                version: Some(negotiation.version),
+                capabilities: Some(Capabilities::host()),
+                codec: Some(negotiation.codec),
+                challenge: Some(challenge.clone()),
+                device_id: None,
+                public_key: None,
+                signature: None,
             };
-            println!("{:?}", message_type);
-            let data: String = SerialData::read_port(port.borrow_mut())?;
-
-            let mut accept_stringifed = String::new();
-            accept_stringifed.push(HANDSHAKE_PREFIX);
-            accept_stringifed.push_str(&accept.to_json_string()?);
-            if Self::check_type_from_str::<HandshakeRequest>(&data) {
-                let parsed: Result<HandshakeRequest, serde_json::Error> =
-                    serde_json::from_str(&data);
-                    println!("RECIEVED SOME REQUEST {:?}", parsed);
-                match parsed {
-                    Ok(result) => match result.handshake_type {
-                        AxonHandshakeType::HandshakeConnect => {
-                            if result.message_type == message_type {
-                                SerialData::write_port(accept_stringifed, port.borrow_mut())?;
-                                Ok(loop {
-                                    let data = SerialData::read_port(port.borrow_mut())?;
-                                    if Self::check_type_from_str::<T>(&data) {
-                                        let message: Message<T> = serde_json::from_str(&data)?;
-                                        break message;
-                                    }
-                                })
-                            } else {
-                                println!("Not the right type");
-                                Err(Error::from(ErrorKind::InvalidData))
-                            }
-                        }
-                        _ => Ok(Message::Empty),
-                    },
-                    Err(_) => Err(Error::from(ErrorKind::InvalidData)),
+            Self::write_frame(port, HANDSHAKE_PREFIX, &accept)?;
+            debug!(
+                target: "axon::handshake",
+                "accepted at protocol v{}",
+                negotiation.version
+            );
+            // device initiated frames answer the host's challenge in the
+            // payload's metadata.
+            let envelope = Self::read_envelope(port)?;
+            Self::paired_devices(&Self::port_name(port.as_ref()))?.verify(
+                request.device_id.as_deref(),
+                &challenge,
+                envelope.signature(),
+            )?;
+            Ok(envelope)
+        }
+
+        fn connect(
+            port: &mut Box<dyn SerialPort>,
+            message_type: AxonMessageType,
+            challenge: &str,
+        ) -> Result<HandshakeResponse, Error> {
+            let port_name = Self::port_name(port.as_ref());
+            let _span = Span::enter("handshake", &port_name);
+            debug!(target: "axon::handshake", "connecting for {:?}", message_type);
+            SequenceTracker::reset_inbound(&port_name);
+            let connect = HandshakeRequest {
+                handshake_type: AxonHandshakeType::HandshakeConnect,
+                message_type,
+                version: Some(PROTOCOL_VERSION),
+                capabilities: Some(Capabilities::host()),
+                challenge: Some(challenge.to_string()),
+                device_id: None,
+            };
+            Self::write_frame(port, HANDSHAKE_PREFIX, &connect)?;
+            // log lines count too, a device that talks but never accepts gives up
+            // the link instead of holding on to it.
+            for _ in 0..MAX_CONNECT_FRAMES {
+                let envelope = match Self::read_frame(port)? {
+                    Frame::Envelope(envelope) => envelope,
+                    Frame::Log(_) => continue,
+                };
+                let response = envelope.open::<HandshakeResponse>()?;
+                if response.handshake_type == AxonHandshakeType::HandshakeAccept {
+                    debug!(
+                        target: "axon::handshake",
+                        "device accepted at protocol v{:?}",
+                        response.version
+                    );
+                    return Ok(response);
                 }
-            } else {
-                Ok(Message::Log {
-                    status: 0,
-                    data: data,
-                })
             }
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "device didn't accept the {:?} handshake within {} frames",
+                    message_type, MAX_CONNECT_FRAMES
+                ),
+            ))
+        }
+
+        // the device has to sign the challenge with its paired key before anything
+        // else happens on the link.
+        pub fn open_session(
+            port: &mut Box<dyn SerialPort>,
+            message_type: AxonMessageType,
+        ) -> Result<Negotiation, Error> {
+            let port_name = Self::port_name(port.as_ref());
+            Metrics::observe_handshake(&port_name, || {
+                let challenge = PairedDevices::challenge()?;
+                let response = Self::connect(port, message_type, &challenge)?;
+                Self::paired_devices(&port_name)?.verify(
+                    response.device_id.as_deref(),
+                    &challenge,
+                    response.signature.as_deref(),
+                )?;
+                let negotiation = response.negotiation()?;
+                // the device picks from the versions the host offered, anything
+                // else means the two sides disagree on the framing that follows.
+                if let Some(version) = response.version {
+                    if version != negotiation.version {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "device accepted protocol v{}, expected v{}",
+                                version, negotiation.version
+                            ),
+                        ));
+                    }
+                }
+                Negotiation::store(&port_name, &negotiation);
+                Ok(negotiation)
+            })
+        }
+
+        // runs the connect/accept exchange on its own, without a payload, to learn
+        // what the device on the other end supports.
+        pub fn negotiate(port: &mut Box<dyn SerialPort>) -> Result<Negotiation, Error> {
+            Self::open_session(port, AxonMessageType::CapabilityMessage)
+        }
+
+        // trust on first use, the device hands over its public key and proves it
+        // holds the matching secret by signing the challenge.
+        pub fn pair(port: &mut Box<dyn SerialPort>, force: bool) -> Result<PairedDevice, Error> {
+            let port_name = Self::port_name(port.as_ref());
+            let challenge = PairedDevices::challenge()?;
+            let response = Metrics::observe_handshake(&port_name, || {
+                Self::connect(port, AxonMessageType::PairMessage, &challenge)
+            })?;
+            let device = match (response.device_id, response.public_key) {
+                (Some(device_id), Some(public_key)) => PairedDevice {
+                    device_id,
+                    public_key,
+                },
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "device did not send an id and public key",
+                    ))
+                }
+            };
+            PairedDevices::check_signature(
+                &device.device_id,
+                &device.public_key,
+                &challenge,
+                response.signature.as_deref(),
+            )?;
+            PairedDevices::pair(
+                &String::from(crate::PAIRED_DEVICES_PATH),
+                device.clone(),
+                force,
+            )?;
+            Ok(device)
         }
 
         pub fn send<T: AxonMessage + Sendable>(
@@ -112,31 +551,19 @@ pub mod handshake {
             prefix_byte: char,
             message_type: AxonMessageType,
         ) -> Result<AxonMessageStatus, Error> {
-            let connect = HandshakeRequest {
-                handshake_type: AxonHandshakeType::HandshakeConnect,
-                message_type: message_type,
-            };
-            let mut connect_stringifed = String::new();
-            connect_stringifed.push(HANDSHAKE_PREFIX);
-            connect_stringifed.push_str(&connect.to_json_string()?);
-            SerialData::write_port(connect_stringifed, port.borrow_mut())?;
-            Ok(loop {
-                let data = SerialData::read_port(port.borrow_mut())?;
-                let result: Result<HandshakeResponse, ()> = serde_json::from_str(&data)?;
-                match result {
-                    Ok(response) => match response.handshake_type {
-                        AxonHandshakeType::HandshakeAccept => {
-                            let mut sendable_stringified = String::new();
-                            sendable_stringified.push(prefix_byte);
-                            sendable_stringified.push_str(&sendable.to_json_string()?);
-                            SerialData::write_port(sendable_stringified, port.borrow_mut())?;
-                            break AxonMessageStatus::Success;
-                        }
-                        _ => (),
-                    },
-                    Err(_) => (),
-                };
-            })
+            let envelope = Envelope::wrap(sendable, None)?;
+            Self::send_envelope(port, envelope, prefix_byte, message_type)
+        }
+
+        pub fn send_envelope(
+            port: &mut Box<dyn SerialPort>,
+            envelope: Envelope,
+            prefix_byte: char,
+            message_type: AxonMessageType,
+        ) -> Result<AxonMessageStatus, Error> {
+            Self::open_session(port, message_type)?.check(&message_type)?;
+            Self::write_envelope(port, prefix_byte, envelope)?;
+            Ok(AxonMessageStatus::Success)
         }
     }
 }
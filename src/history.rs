@@ -0,0 +1,330 @@
+pub mod record_history {
+
+    use crate::record::record::Record;
+    use crate::timesync::time_sync::TimeSync;
+    use serde::Deserialize;
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::prelude::*;
+    use std::io::Result as SingleResult;
+    use std::io::{BufReader, Error, ErrorKind};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    pub const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+    pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+    const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+    const FILE_EXTENSION: &str = "ndjson";
+    // appends between size checks on the same day.
+    const PRUNE_EVERY: u64 = 1000;
+
+    static STORE: Mutex<Option<RecordStore>> = Mutex::new(None);
+
+    // every field narrows the result, `limit` keeps the newest records.
+    #[derive(Deserialize, Debug, Default, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RecordQuery {
+        pub device_id: Option<String>,
+        pub sensor: Option<String>,
+        pub from: Option<u64>,
+        pub to: Option<u64>,
+        pub limit: Option<usize>,
+    }
+
+    // one directory per device, one file of JSON lines per day inside it, so a
+    // query only opens the files that can match. there is no sensor index, a
+    // record can carry any number of sensors and the sensor filter only runs
+    // over the lines of the device and days that are read anyway.
+    #[derive(Clone)]
+    pub struct RecordStore {
+        path: String,
+        max_age_days: u64,
+        max_bytes: u64,
+        // shared between clones, so the copy behind `RecordHistory` and the
+        // ones handed out to readers agree on when the store was last pruned.
+        pruned_day: Arc<AtomicU64>,
+        appends: Arc<AtomicU64>,
+    }
+
+    struct DayFile {
+        day: u64,
+        path: PathBuf,
+        size: u64,
+    }
+
+    impl RecordQuery {
+        fn matches(&self, record: &Record, received_at: u64) -> bool {
+            self.from.is_none_or(|from| received_at >= from)
+                && self.to.is_none_or(|to| received_at <= to)
+                && self
+                    .device_id
+                    .as_ref()
+                    .is_none_or(|device_id| record.device_id() == device_id)
+                && self
+                    .sensor
+                    .as_ref()
+                    .is_none_or(|sensor| record.has_sensor(sensor))
+        }
+    }
+
+    impl RecordStore {
+        pub fn new(path: String, max_age_days: u64, max_bytes: u64) -> RecordStore {
+            RecordStore {
+                path,
+                max_age_days,
+                max_bytes,
+                pruned_day: Arc::new(AtomicU64::new(u64::MAX)),
+                appends: Arc::new(AtomicU64::new(0)),
+            }
+        }
+
+        // device ids come off the wire, hex keeps them from naming anything
+        // outside the store.
+        fn device_dir(&self, device_id: &str) -> PathBuf {
+            Path::new(&self.path).join(hex::encode(device_id))
+        }
+
+        fn day_files(dir: &Path) -> SingleResult<Vec<DayFile>> {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some(FILE_EXTENSION) {
+                    continue;
+                }
+                let day = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok());
+                if let Some(day) = day {
+                    let size = fs::metadata(&path)?.len();
+                    files.push(DayFile { day, path, size });
+                }
+            }
+            Ok(files)
+        }
+
+        fn all_files(&self) -> SingleResult<Vec<DayFile>> {
+            if !Path::new(&self.path).exists() {
+                return Ok(Vec::new());
+            }
+            let mut files = Vec::new();
+            for entry in fs::read_dir(&self.path)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    files.append(&mut Self::day_files(&path)?);
+                }
+            }
+            Ok(files)
+        }
+
+        pub fn append(&self, record: &Record) -> SingleResult<()> {
+            let received_at = record.received_at().unwrap_or_else(TimeSync::now);
+            let dir = self.device_dir(record.device_id());
+            fs::create_dir_all(&dir)?;
+            let mut line = serde_json::to_string(record)?;
+            line.push('\n');
+            let mut day_file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(format!("{}.{}", received_at / DAY_MILLIS, FILE_EXTENSION)))?;
+            day_file.write_all(line.as_bytes())?;
+            // a full pass over the store once a day, or every `PRUNE_EVERY`
+            // records for a busy one, rather than on every record.
+            let appends = self.appends.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.pruned_day.load(Ordering::SeqCst) != received_at / DAY_MILLIS
+                || appends >= PRUNE_EVERY
+            {
+                self.prune(received_at)?;
+            }
+            Ok(())
+        }
+
+        // drops whole days, older than `max_age_days` first and then the oldest
+        // until the store fits in `max_bytes`. today's files are never dropped.
+        pub fn prune(&self, now: u64) -> SingleResult<()> {
+            let today = now / DAY_MILLIS;
+            self.pruned_day.store(today, Ordering::SeqCst);
+            self.appends.store(0, Ordering::SeqCst);
+            let mut files = self.all_files()?;
+            files.sort_by_key(|file| file.day);
+            let mut total: u64 = files.iter().map(|file| file.size).sum();
+            for file in files.iter().filter(|file| file.day < today) {
+                if today - file.day < self.max_age_days && total <= self.max_bytes {
+                    continue;
+                }
+                debug!(target: "axon::record", "pruning {}", file.path.display());
+                fs::remove_file(&file.path)?;
+                total -= file.size;
+            }
+            Ok(())
+        }
+
+        // calls `f` with every matching record, oldest first, ignoring `limit`.
+        // only one day is held in memory at a time, so this is safe to run over
+        // the whole store.
+        pub fn scan<F>(&self, query: &RecordQuery, mut f: F) -> SingleResult<()>
+        where
+            F: FnMut(Record) -> SingleResult<()>,
+        {
+            let mut files = match query.device_id {
+                Some(ref device_id) if self.device_dir(device_id).exists() => {
+                    Self::day_files(&self.device_dir(device_id))?
+                }
+                Some(_) => Vec::new(),
+                None => self.all_files()?,
+            };
+            let first_day = query.from.map_or(0, |from| from / DAY_MILLIS);
+            let last_day = query.to.map_or(u64::MAX, |to| to / DAY_MILLIS);
+            files.retain(|file| file.day >= first_day && file.day <= last_day);
+            files.sort_by_key(|file| file.day);
+            for day in files.chunk_by(|a, b| a.day == b.day) {
+                let mut records = Vec::new();
+                for file in day {
+                    for line in BufReader::new(fs::File::open(&file.path)?).lines() {
+                        // a line cut short by a crash mid write is skipped, not fatal.
+                        let record: Record = match serde_json::from_str(&line?) {
+                            Ok(record) => record,
+                            Err(_) => continue,
+                        };
+                        let received_at = record.received_at().unwrap_or_default();
+                        if query.matches(&record, received_at) {
+                            records.push((received_at, record));
+                        }
+                    }
+                }
+                records.sort_by_key(|(received_at, _)| *received_at);
+                for (_, record) in records {
+                    f(record)?;
+                }
+            }
+            Ok(())
+        }
+
+        pub fn query(&self, query: &RecordQuery) -> SingleResult<Vec<Record>> {
+            if query.limit == Some(0) {
+                return Ok(Vec::new());
+            }
+            let mut records = VecDeque::new();
+            self.scan(query, |record| {
+                if query.limit == Some(records.len()) {
+                    records.pop_front();
+                }
+                records.push_back(record);
+                Ok(())
+            })?;
+            Ok(records.into_iter().collect())
+        }
+    }
+
+    pub struct RecordHistory;
+
+    impl RecordHistory {
+        pub fn set_store(store: Option<RecordStore>) {
+            if let Some(ref store) = store {
+                if let Err(e) = store.prune(TimeSync::now()) {
+                    warn!(target: "axon::record", "could not prune records: {}", e);
+                }
+            }
+            *STORE.lock().unwrap() = store;
+        }
+
+        pub fn open_default() {
+            Self::set_store(Some(RecordStore::new(
+                String::from(crate::RECORDS_PATH),
+                DEFAULT_MAX_AGE_DAYS,
+                DEFAULT_MAX_BYTES,
+            )));
+        }
+
+        // a full disk shouldn't take the serial link down with it. the write
+        // happens on a copy, so readers and other ports don't wait on it.
+        pub fn store(record: &Record) {
+            let store = STORE.lock().unwrap().clone();
+            if let Some(store) = store {
+                if let Err(e) = store.append(record) {
+                    warn!(target: "axon::record", "could not store record: {}", e);
+                }
+            }
+        }
+
+        // a copy of the open store, so long reads don't hold up new records.
+        pub fn current() -> SingleResult<RecordStore> {
+            match STORE.lock().unwrap().as_ref() {
+                Some(store) => Ok(store.clone()),
+                None => Err(Error::new(
+                    ErrorKind::NotFound,
+                    "record history is not open, call init first",
+                )),
+            }
+        }
+
+        pub fn query(query: &RecordQuery) -> SingleResult<Vec<Record>> {
+            Self::current()?.query(query)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::record_history::{RecordQuery, RecordStore};
+    use crate::record::fixture;
+    use crate::record::record::Record;
+    use std::env;
+    use std::fs;
+
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+
+    fn record(device_id: &str, sensor: &str, received_at: u64) -> Record {
+        let mut record = fixture::record(device_id, sensor, "21.5");
+        record.stamp("/dev/test-history", received_at);
+        record
+    }
+
+    #[test]
+    fn store_query_and_prune() {
+        let mut dir = env::temp_dir();
+        dir.push("axon-record-history-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.to_str().unwrap().to_string();
+        let start = 1_700_000_000_000 / DAY * DAY;
+        let store = RecordStore::new(path.clone(), 30, u64::MAX);
+        store.append(&record("pump", "temperature", start)).unwrap();
+        store.append(&record("pump", "flow", start + 1)).unwrap();
+        store
+            .append(&record("../tank", "temperature", start + DAY))
+            .unwrap();
+        store
+            .append(&record("pump", "temperature", start + DAY + 1))
+            .unwrap();
+
+        let temperature = RecordQuery {
+            sensor: Some(String::from("temperature")),
+            ..RecordQuery::default()
+        };
+        let found = store.query(&temperature).unwrap();
+        let times: Vec<Option<u64>> = found.iter().map(|record| record.received_at()).collect();
+        assert_eq!(
+            times,
+            vec![Some(start), Some(start + DAY), Some(start + DAY + 1)]
+        );
+
+        let latest = RecordQuery {
+            device_id: Some(String::from("pump")),
+            from: Some(start + 1),
+            limit: Some(1),
+            ..temperature.clone()
+        };
+        let found = store.query(&latest).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].received_at(), Some(start + DAY + 1));
+        assert!(!dir.join("..").join("tank").exists());
+
+        // a month on, only the newest day is still inside the retention window.
+        let later = RecordStore::new(path, 30, u64::MAX);
+        later.prune(start + 30 * DAY).unwrap();
+        assert_eq!(later.query(&RecordQuery::default()).unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,6 +5,7 @@ pub mod device_identity {
     use rand::{os, Rng};
     use serde::{Deserialize, Serialize};
     use std::collections::hash_map::DefaultHasher;
+    use std::fs;
     use std::fs::File;
     use std::hash::{Hash, Hasher};
     use std::io::prelude::*;
@@ -23,7 +24,7 @@ pub mod device_identity {
             let mut hasher = DefaultHasher::new();
             let reader = BufReader::new(file);
             let serial: String = reader.lines().last().unwrap()?;
-            println!("{}", serial);
+            trace!(target: "axon::identity", "hashing the cpu serial into a device id");
             serial.hash(&mut hasher);
             Ok(hasher.finish().to_string())
         }
@@ -71,6 +72,15 @@ pub mod device_identity {
             Ok(())
         }
 
+        // the identity file is read-only once written, so it has to be removed
+        // before a new one can take its place.
+        pub fn replace_identity(path: &String) -> SingleResult<()> {
+            if Self::check_identity(path)? {
+                fs::remove_file(path)?;
+            }
+            Self::create_identity(path)
+        }
+
         pub fn identity(path: String) -> SingleResult<String> {
             match Identity::check_identity(&path) {
                 Ok(true) => Identity::load_identity_from_path(&path),
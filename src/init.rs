@@ -1,5 +1,6 @@
 pub mod init {
 
+    use crate::history::record_history::RecordHistory;
     use crate::idenity::device_identity::Identity;
     use crate::state::device_state::State;
     use std::io::Result as SingleResult;
@@ -13,14 +14,16 @@ pub mod init {
 
         pub fn init_fs() -> SingleResult<()> {
             match Self::fs_exists() {
-                Ok(true) => Ok(()),
+                Ok(true) => (),
                 Ok(false) => {
                     State::init_state(&String::from(crate::PARENT_PATH), &String::from(crate::STATE_PATH))?;
                     Identity::create_identity(&crate::IDENTITY_PATH.to_string())?;
-                    Ok(())
                 }
-                Err(err) => Err(err),
+                Err(err) => return Err(err),
             }
+            // records are kept from here on, `setRecordStore` moves or stops them.
+            RecordHistory::open_default();
+            Ok(())
         }
     }
 }
@@ -1,33 +1,79 @@
+#[cfg(feature = "node")]
 #[macro_use]
 extern crate neon;
+extern crate ciborium;
 extern crate crypto;
 extern crate hex;
+#[macro_use]
+extern crate log;
 extern crate rand;
+extern crate rmp_serde;
+extern crate schemars;
 extern crate serde;
 extern crate serde_json;
 extern crate serialport;
+extern crate toml;
 
 pub mod axonmessage;
+#[cfg(feature = "node")]
 pub mod bindings;
+pub mod capability;
+pub mod capture;
+pub mod codec;
 pub mod command;
+pub mod devicelog;
+pub mod export;
+pub mod firmware;
 pub mod handshake;
+pub mod history;
 pub mod idenity;
 pub mod init;
+pub mod logging;
+pub mod metrics;
+pub mod pairing;
+pub mod query;
 pub mod record;
+pub mod registry;
+pub mod rules;
+pub mod schedule;
+pub mod schema;
+pub mod sequence;
 pub mod serial;
 pub mod state;
+pub mod supervisor;
+pub mod timesync;
 
+#[cfg(feature = "node")]
 use bindings::bindings::{
-    axon_init, load_identity, load_state, save_state, send_command, serial_read, serial_rw,
-    serial_write, watch_record, watch_state,
+    add_paired_device, add_schedule, analog_write, axon_init, device_logs, digital_write, dispense,
+    export_records, get_schemas, get_stats, list_message_types, list_paired_devices, list_rules,
+    list_schedules, load_identity, load_rules, load_state, log_level, negotiate, pair_device,
+    poll_device_logs, poll_logs, provision_device, pulse, query, query_records, read_pin,
+    recieve_message, register_message_type, remove_schedule, replay_record, replay_state,
+    rule_hits, save_state, send_command, send_message, sequence_stats, serial_read, serial_rw,
+    serial_write, servo_write, set_device_log_file, set_log_level, set_record_store, set_rules,
+    set_strict_validation, start_capture, start_scheduler, stop_capture, stop_scheduler,
+    sync_clock, unpair_device, update_schedule, watch_record, watch_state, write_schemas,
+    JsDeviceSupervisor,
 };
 
 pub const PARENT_PATH: &'static str = "/axon";
 pub const IDENTITY_PATH: &'static str = "/axon/axon-identity.json";
 pub const STATE_PATH: &'static str = "/axon/axon-state.json";
+pub const PAIRED_DEVICES_PATH: &str = "/axon/axon-paired-devices.json";
+pub const RECORDS_PATH: &str = "/axon/records";
+pub const SCHEDULES_PATH: &str = "/axon/axon-schedules.json";
 
+#[cfg(feature = "node")]
 register_module!(mut m, {
     m.export_function("sendCommand", send_command)?;
+    m.export_function("digitalWrite", digital_write)?;
+    m.export_function("analogWrite", analog_write)?;
+    m.export_function("pulse", pulse)?;
+    m.export_function("servoWrite", servo_write)?;
+    m.export_function("readPin", read_pin)?;
+    m.export_function("query", query)?;
+    m.export_function("dispense", dispense)?;
     m.export_function("loadIdentity", load_identity)?;
     m.export_function("readSerial", serial_read)?;
     m.export_function("writeSerial", serial_write)?;
@@ -35,7 +81,47 @@ register_module!(mut m, {
     m.export_function("saveState", save_state)?;
     m.export_function("loadState", load_state)?;
     m.export_function("watchState", watch_state)?;
+    m.export_function("provisionDevice", provision_device)?;
     m.export_function("watchRecord", watch_record)?;
+    m.export_function("syncClock", sync_clock)?;
+    m.export_function("queryRecords", query_records)?;
+    m.export_function("setRecordStore", set_record_store)?;
+    m.export_function("exportRecords", export_records)?;
+    m.export_function("loadRules", load_rules)?;
+    m.export_function("setRules", set_rules)?;
+    m.export_function("listRules", list_rules)?;
+    m.export_function("ruleHits", rule_hits)?;
+    m.export_function("addSchedule", add_schedule)?;
+    m.export_function("updateSchedule", update_schedule)?;
+    m.export_function("removeSchedule", remove_schedule)?;
+    m.export_function("listSchedules", list_schedules)?;
+    m.export_function("startScheduler", start_scheduler)?;
+    m.export_function("stopScheduler", stop_scheduler)?;
     m.export_function("init", axon_init)?;
+    m.export_function("sequenceStats", sequence_stats)?;
+    m.export_function("getStats", get_stats)?;
+    m.export_function("startCapture", start_capture)?;
+    m.export_function("stopCapture", stop_capture)?;
+    m.export_function("replayRecord", replay_record)?;
+    m.export_function("replayState", replay_state)?;
+    m.export_function("negotiate", negotiate)?;
+    m.export_function("pairDevice", pair_device)?;
+    m.export_function("addPairedDevice", add_paired_device)?;
+    m.export_function("unpairDevice", unpair_device)?;
+    m.export_function("listPairedDevices", list_paired_devices)?;
+    m.export_function("registerMessageType", register_message_type)?;
+    m.export_function("listMessageTypes", list_message_types)?;
+    m.export_function("getSchemas", get_schemas)?;
+    m.export_function("writeSchemas", write_schemas)?;
+    m.export_function("setStrictValidation", set_strict_validation)?;
+    m.export_function("sendMessage", send_message)?;
+    m.export_function("recieveMessage", recieve_message)?;
+    m.export_function("deviceLogs", device_logs)?;
+    m.export_function("pollDeviceLogs", poll_device_logs)?;
+    m.export_function("setDeviceLogFile", set_device_log_file)?;
+    m.export_function("setLogLevel", set_log_level)?;
+    m.export_function("logLevel", log_level)?;
+    m.export_function("pollLogs", poll_logs)?;
+    m.export_class::<JsDeviceSupervisor>("DeviceSupervisor")?;
     Ok(())
 });
@@ -0,0 +1,176 @@
+pub mod host_log {
+
+    use log::{LevelFilter, Log, Metadata, Record};
+    use serde::{Deserialize, Serialize};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::{Error, ErrorKind};
+    use std::sync::{Condvar, Mutex, Once};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub const LOG_CAPACITY: usize = 512;
+
+    static LOGGER: HostLogger = HostLogger {
+        records: Mutex::new(RecordBuffer {
+            next_id: 1,
+            entries: VecDeque::new(),
+        }),
+        new_records: Condvar::new(),
+    };
+    static INSTALL: Once = Once::new();
+
+    thread_local! {
+        static SPANS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct LogRecord {
+        pub id: u64,
+        pub level: String,
+        pub target: String,
+        pub message: String,
+        // the protocol spans open on the logging thread, outermost first.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub spans: Vec<String>,
+        pub timestamp: u64,
+    }
+
+    struct RecordBuffer {
+        next_id: u64,
+        entries: VecDeque<LogRecord>,
+    }
+
+    struct HostLogger {
+        records: Mutex<RecordBuffer>,
+        new_records: Condvar,
+    }
+
+    // marks a stretch of protocol work, e.g. one handshake. every record logged
+    // on this thread while the span is alive carries its name.
+    pub struct Span {
+        name: String,
+    }
+
+    impl Span {
+        pub fn enter(name: &str, detail: &str) -> Span {
+            SPANS.with(|spans| spans.borrow_mut().push(name.to_string()));
+            debug!(target: "axon::span", "enter {} {}", name, detail);
+            Span {
+                name: name.to_string(),
+            }
+        }
+    }
+
+    impl Drop for Span {
+        fn drop(&mut self) {
+            debug!(target: "axon::span", "exit {}", self.name);
+            SPANS.with(|spans| {
+                spans.borrow_mut().pop();
+            });
+        }
+    }
+
+    impl Log for HostLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            let spans = SPANS.with(|spans| spans.borrow().clone());
+            let mut records = self.records.lock().unwrap();
+            let id = records.next_id;
+            records.next_id += 1;
+            if records.entries.len() == LOG_CAPACITY {
+                records.entries.pop_front();
+            }
+            records.entries.push_back(LogRecord {
+                id,
+                level: record.level().to_string().to_lowercase(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+                spans,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_millis() as u64)
+                    .unwrap_or_default(),
+            });
+            self.new_records.notify_all();
+        }
+
+        fn flush(&self) {}
+    }
+
+    pub struct HostLog;
+
+    impl HostLog {
+        // nothing is recorded until a level is set, the default is silent.
+        pub fn set_level(level: &str) -> Result<LevelFilter, Error> {
+            let filter: LevelFilter = level.parse().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown log level `{}`", level),
+                )
+            })?;
+            INSTALL.call_once(|| {
+                let _ = log::set_logger(&LOGGER);
+            });
+            log::set_max_level(filter);
+            Ok(filter)
+        }
+
+        pub fn level() -> LevelFilter {
+            log::max_level()
+        }
+
+        pub fn since(cursor: u64) -> Vec<LogRecord> {
+            Self::collect(&LOGGER.records.lock().unwrap(), cursor)
+        }
+
+        // blocks until a record newer than `cursor` arrives or `timeout` passes.
+        pub fn wait(cursor: u64, timeout: Duration) -> Vec<LogRecord> {
+            let records = LOGGER.records.lock().unwrap();
+            let (records, _) = LOGGER
+                .new_records
+                .wait_timeout_while(records, timeout, |records| records.next_id <= cursor + 1)
+                .unwrap();
+            Self::collect(&records, cursor)
+        }
+
+        fn collect(records: &RecordBuffer, cursor: u64) -> Vec<LogRecord> {
+            records
+                .entries
+                .iter()
+                .filter(|record| record.id > cursor)
+                .cloned()
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::host_log::{HostLog, Span};
+    use log::LevelFilter;
+
+    #[test]
+    fn records_with_spans() {
+        assert!(HostLog::set_level("chatty").is_err());
+        assert_eq!(HostLog::set_level("debug").unwrap(), LevelFilter::Debug);
+        {
+            let _span = Span::enter("handshake", "/dev/test-logging");
+            info!(target: "axon::test", "accepted");
+        }
+        let records: Vec<_> = HostLog::since(0)
+            .into_iter()
+            .filter(|record| record.target == "axon::test")
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "accepted");
+        assert_eq!(records[0].spans, vec!["handshake"]);
+        assert_eq!(records[0].level, "info");
+    }
+}
@@ -0,0 +1,284 @@
+pub mod link_metrics {
+
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+    use std::io::{Error, ErrorKind};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    // upper bounds of the latency buckets, in milliseconds.
+    pub const LATENCY_BUCKETS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+    static PORTS: Mutex<BTreeMap<String, PortMetrics>> = Mutex::new(BTreeMap::new());
+
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Histogram {
+        // one count per bucket in `LATENCY_BUCKETS`, not cumulative. anything
+        // slower than the last bucket only shows up in `count`.
+        pub buckets: Vec<u64>,
+        pub count: u64,
+        pub sum_millis: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PortMetrics {
+        pub frames_sent: u64,
+        pub frames_received: u64,
+        pub bytes_sent: u64,
+        pub bytes_received: u64,
+        pub handshake_attempts: u64,
+        pub handshake_successes: u64,
+        pub handshake_failures: u64,
+        pub decode_errors: u64,
+        pub timeouts: u64,
+        pub handshake_latency: Histogram,
+    }
+
+    impl PortMetrics {
+        // name, help text and value of every plain counter.
+        pub fn counters(&self) -> [(&'static str, &'static str, u64); 9] {
+            [
+                (
+                    "frames_sent",
+                    "Frames written to the device.",
+                    self.frames_sent,
+                ),
+                (
+                    "frames_received",
+                    "Frames read from the device.",
+                    self.frames_received,
+                ),
+                (
+                    "bytes_sent",
+                    "Bytes written to the device.",
+                    self.bytes_sent,
+                ),
+                (
+                    "bytes_received",
+                    "Bytes read from the device.",
+                    self.bytes_received,
+                ),
+                (
+                    "handshake_attempts",
+                    "Handshakes started.",
+                    self.handshake_attempts,
+                ),
+                (
+                    "handshake_successes",
+                    "Handshakes completed.",
+                    self.handshake_successes,
+                ),
+                (
+                    "handshake_failures",
+                    "Handshakes that failed.",
+                    self.handshake_failures,
+                ),
+                (
+                    "decode_errors",
+                    "Lines that could not be decoded.",
+                    self.decode_errors,
+                ),
+                ("timeouts", "Reads that timed out.", self.timeouts),
+            ]
+        }
+    }
+
+    impl Histogram {
+        pub fn observe(&mut self, elapsed: Duration) {
+            let millis = elapsed.as_millis() as u64;
+            if self.buckets.len() != LATENCY_BUCKETS.len() {
+                self.buckets = vec![0; LATENCY_BUCKETS.len()];
+            }
+            if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| millis <= *bound) {
+                self.buckets[bucket] += 1;
+            }
+            self.count += 1;
+            self.sum_millis += millis;
+        }
+    }
+
+    pub struct Metrics;
+
+    impl Metrics {
+        fn update<F: FnOnce(&mut PortMetrics)>(port_name: &str, f: F) {
+            let mut ports = PORTS.lock().unwrap();
+            f(ports.entry(port_name.to_string()).or_default());
+        }
+
+        pub fn frame_sent(port_name: &str, bytes: usize) {
+            Self::update(port_name, |metrics| {
+                metrics.frames_sent += 1;
+                metrics.bytes_sent += bytes as u64;
+            });
+        }
+
+        // device log lines count towards bytes but aren't frames.
+        pub fn line_received(port_name: &str, bytes: usize, is_frame: bool) {
+            Self::update(port_name, |metrics| {
+                metrics.bytes_received += bytes as u64;
+                if is_frame {
+                    metrics.frames_received += 1;
+                }
+            });
+        }
+
+        pub fn read_failed(port_name: &str, error: &Error) {
+            Self::update(port_name, |metrics| match error.kind() {
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => metrics.timeouts += 1,
+                ErrorKind::InvalidData => metrics.decode_errors += 1,
+                _ => (),
+            });
+        }
+
+        // times `handshake` and counts it as a success or failure.
+        pub fn observe_handshake<T, F>(port_name: &str, handshake: F) -> Result<T, Error>
+        where
+            F: FnOnce() -> Result<T, Error>,
+        {
+            Self::update(port_name, |metrics| metrics.handshake_attempts += 1);
+            let started = Instant::now();
+            let result = handshake();
+            let elapsed = started.elapsed();
+            Self::update(port_name, |metrics| {
+                if result.is_ok() {
+                    metrics.handshake_successes += 1;
+                    metrics.handshake_latency.observe(elapsed);
+                } else {
+                    metrics.handshake_failures += 1;
+                }
+            });
+            result
+        }
+
+        pub fn port(port_name: &str) -> PortMetrics {
+            PORTS
+                .lock()
+                .unwrap()
+                .get(port_name)
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        pub fn all() -> BTreeMap<String, PortMetrics> {
+            PORTS.lock().unwrap().clone()
+        }
+
+        pub fn prometheus() -> String {
+            Self::render_prometheus(&Self::all())
+        }
+
+        pub fn render_prometheus(ports: &BTreeMap<String, PortMetrics>) -> String {
+            let mut text = String::new();
+            let empty = PortMetrics::default();
+            for (i, (name, help, _)) in empty.counters().iter().enumerate() {
+                let _ = writeln!(text, "# HELP axon_{}_total {}", name, help);
+                let _ = writeln!(text, "# TYPE axon_{}_total counter", name);
+                for (port, metrics) in ports {
+                    let _ = writeln!(
+                        text,
+                        "axon_{}_total{{port=\"{}\"}} {}",
+                        name,
+                        escape_label(port),
+                        metrics.counters()[i].2
+                    );
+                }
+            }
+
+            let name = "axon_handshake_latency_seconds";
+            let _ = writeln!(text, "# HELP {} Time taken by successful handshakes.", name);
+            let _ = writeln!(text, "# TYPE {} histogram", name);
+            for (port, metrics) in ports {
+                let port = escape_label(port);
+                let latency = &metrics.handshake_latency;
+                let mut cumulative = 0;
+                for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                    cumulative += latency.buckets.get(i).cloned().unwrap_or(0);
+                    let _ = writeln!(
+                        text,
+                        "{}_bucket{{port=\"{}\",le=\"{}\"}} {}",
+                        name,
+                        port,
+                        *bound as f64 / 1000.0,
+                        cumulative
+                    );
+                }
+                let _ = writeln!(
+                    text,
+                    "{}_bucket{{port=\"{}\",le=\"+Inf\"}} {}",
+                    name, port, latency.count
+                );
+                let _ = writeln!(
+                    text,
+                    "{}_sum{{port=\"{}\"}} {}",
+                    name,
+                    port,
+                    latency.sum_millis as f64 / 1000.0
+                );
+                let _ = writeln!(
+                    text,
+                    "{}_count{{port=\"{}\"}} {}",
+                    name, port, latency.count
+                );
+            }
+            text
+        }
+    }
+
+    fn escape_label(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::link_metrics::{Metrics, PortMetrics};
+    use std::collections::BTreeMap;
+    use std::io::{Error, ErrorKind};
+    use std::time::Duration;
+
+    #[test]
+    fn counters_and_prometheus() {
+        let port = "/dev/test-metrics";
+        Metrics::frame_sent(port, 40);
+        Metrics::line_received(port, 12, false);
+        Metrics::line_received(port, 30, true);
+        Metrics::read_failed(port, &Error::from(ErrorKind::TimedOut));
+        assert!(Metrics::observe_handshake(port, || Ok(())).is_ok());
+        assert!(Metrics::observe_handshake::<(), _>(port, || {
+            Err(Error::from(ErrorKind::PermissionDenied))
+        })
+        .is_err());
+
+        let metrics = Metrics::port(port);
+        assert_eq!(metrics.frames_sent, 1);
+        assert_eq!(metrics.frames_received, 1);
+        assert_eq!(metrics.bytes_received, 42);
+        assert_eq!(metrics.timeouts, 1);
+        assert_eq!(metrics.handshake_attempts, 2);
+        assert_eq!(metrics.handshake_failures, 1);
+        assert_eq!(metrics.handshake_latency.count, 1);
+
+        let mut latency = PortMetrics::default();
+        latency.handshake_latency.observe(Duration::from_millis(30));
+        latency
+            .handshake_latency
+            .observe(Duration::from_millis(20000));
+        let mut ports = BTreeMap::new();
+        ports.insert(String::from("/dev/ttyACM0"), latency);
+        let text = Metrics::render_prometheus(&ports);
+        assert!(text.contains("axon_frames_sent_total{port=\"/dev/ttyACM0\"} 0"));
+        assert!(text.contains(
+            "axon_handshake_latency_seconds_bucket{port=\"/dev/ttyACM0\",le=\"0.05\"} 1"
+        ));
+        assert!(text.contains(
+            "axon_handshake_latency_seconds_bucket{port=\"/dev/ttyACM0\",le=\"+Inf\"} 2"
+        ));
+        assert!(text.contains("axon_handshake_latency_seconds_sum{port=\"/dev/ttyACM0\"} 20.03"));
+    }
+}
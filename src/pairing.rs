@@ -0,0 +1,262 @@
+pub mod device_pairing {
+
+    use crypto::ed25519;
+    use hex;
+    use rand::{os, Rng};
+    use serde::{Deserialize, Serialize};
+    use std::error::Error as StdError;
+    use std::fmt;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::io::Result as SingleResult;
+    use std::io::{Error, ErrorKind};
+    use std::path::Path;
+
+    const CHALLENGE_LENGTH: usize = 32;
+    const PUBLIC_KEY_LENGTH: usize = 32;
+    const SIGNATURE_LENGTH: usize = 64;
+
+    #[derive(Debug, PartialEq)]
+    pub enum AuthError {
+        Anonymous,
+        UnknownDevice(String),
+        BadSignature(String),
+    }
+
+    impl fmt::Display for AuthError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                AuthError::Anonymous => write!(f, "device did not identify itself"),
+                AuthError::UnknownDevice(id) => write!(f, "device {} is not paired", id),
+                AuthError::BadSignature(id) => {
+                    write!(f, "device {} failed the challenge, refusing", id)
+                }
+            }
+        }
+    }
+
+    impl StdError for AuthError {}
+
+    impl From<AuthError> for Error {
+        fn from(error: AuthError) -> Error {
+            Error::new(ErrorKind::PermissionDenied, error)
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PairedDevice {
+        pub device_id: String,
+        pub public_key: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default)]
+    pub struct PairedDevices {
+        devices: Vec<PairedDevice>,
+    }
+
+    impl PairedDevices {
+        pub fn challenge() -> SingleResult<String> {
+            let mut os_rng = os::OsRng::new()?;
+            let mut nonce: [u8; CHALLENGE_LENGTH] = [0; CHALLENGE_LENGTH];
+            os_rng.fill_bytes(&mut nonce);
+            Ok(hex::encode_upper(nonce))
+        }
+
+        pub fn load(path: &String) -> SingleResult<PairedDevices> {
+            if !Path::new(path).exists() {
+                return Ok(PairedDevices::default());
+            }
+            let mut paired_file = File::open(path)?;
+            let mut paired_string = String::new();
+            paired_file.read_to_string(&mut paired_string)?;
+            Ok(serde_json::from_str(&paired_string)?)
+        }
+
+        pub fn save(&self, path: &String) -> SingleResult<()> {
+            let mut paired_file = File::create(path)?;
+            paired_file.write_all(serde_json::to_string(&self)?.as_bytes())?;
+            Ok(())
+        }
+
+        pub fn devices(&self) -> &Vec<PairedDevice> {
+            &self.devices
+        }
+
+        pub fn find(&self, device_id: &str) -> Option<&PairedDevice> {
+            self.devices
+                .iter()
+                .find(|device| device.device_id == device_id)
+        }
+
+        // a device claiming an id that is already paired to another key is
+        // refused, replacing it takes an unpair first or `force`.
+        pub fn pair(path: &String, device: PairedDevice, force: bool) -> SingleResult<()> {
+            match hex::decode(&device.public_key) {
+                Ok(ref key) if key.len() == PUBLIC_KEY_LENGTH => (),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "public key for {} must be {} hex encoded bytes",
+                            device.device_id, PUBLIC_KEY_LENGTH
+                        ),
+                    ))
+                }
+            }
+            let mut paired = Self::load(path)?;
+            match paired.find(&device.device_id) {
+                Some(existing) if existing.public_key.eq_ignore_ascii_case(&device.public_key) => {
+                    return Ok(())
+                }
+                Some(_) if !force => {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!(
+                            "{} is already paired with another key, unpair it first",
+                            device.device_id
+                        ),
+                    ))
+                }
+                _ => (),
+            }
+            paired
+                .devices
+                .retain(|paired| paired.device_id != device.device_id);
+            paired.devices.push(device);
+            paired.save(path)
+        }
+
+        pub fn unpair(path: &String, device_id: &str) -> SingleResult<bool> {
+            let mut paired = Self::load(path)?;
+            let before = paired.devices.len();
+            paired
+                .devices
+                .retain(|device| device.device_id != device_id);
+            paired.save(path)?;
+            Ok(paired.devices.len() != before)
+        }
+
+        pub fn check_signature(
+            device_id: &str,
+            public_key: &str,
+            challenge: &str,
+            signature: Option<&str>,
+        ) -> Result<(), AuthError> {
+            // ed25519::verify slices both without checking, so anything the
+            // wrong length has to be turned away before it gets there.
+            let verified = match (hex::decode(public_key), hex::decode(challenge), signature) {
+                (Ok(ref key), Ok(ref challenge), Some(signature))
+                    if key.len() == PUBLIC_KEY_LENGTH =>
+                {
+                    match hex::decode(signature) {
+                        Ok(ref signature) if signature.len() == SIGNATURE_LENGTH => {
+                            ed25519::verify(challenge, key, signature)
+                        }
+                        _ => false,
+                    }
+                }
+                _ => false,
+            };
+            if verified {
+                Ok(())
+            } else {
+                Err(AuthError::BadSignature(device_id.to_string()))
+            }
+        }
+
+        // authentication only kicks in once at least one device has been paired.
+        pub fn verify(
+            &self,
+            device_id: Option<&str>,
+            challenge: &str,
+            signature: Option<&str>,
+        ) -> Result<(), AuthError> {
+            if self.devices.is_empty() {
+                return Ok(());
+            }
+            let device_id = device_id.ok_or(AuthError::Anonymous)?;
+            let device = self
+                .find(device_id)
+                .ok_or_else(|| AuthError::UnknownDevice(device_id.to_string()))?;
+            Self::check_signature(device_id, &device.public_key, challenge, signature)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pairing::device_pairing::{AuthError, PairedDevice, PairedDevices};
+    use crypto::ed25519;
+    use std::path::PathBuf;
+
+    #[test]
+    fn verify_challenge() {
+        let mut path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test_files/state/paired_devices.json");
+        let path = String::from(path.to_str().unwrap());
+
+        let (secret, public) = ed25519::keypair(&[7; 32]);
+        PairedDevices::pair(
+            &path,
+            PairedDevice {
+                device_id: String::from("axon-01"),
+                public_key: hex::encode_upper(public),
+            },
+            true,
+        )
+        .unwrap();
+        let (_, imposter) = ed25519::keypair(&[8; 32]);
+        let takeover = PairedDevice {
+            device_id: String::from("axon-01"),
+            public_key: hex::encode_upper(imposter),
+        };
+        assert!(PairedDevices::pair(&path, takeover, false).is_err());
+        let paired = PairedDevices::load(&path).unwrap();
+
+        let challenge = PairedDevices::challenge().unwrap();
+        let signature =
+            hex::encode(&ed25519::signature(&hex::decode(&challenge).unwrap(), &secret)[..]);
+        let (spoofed, _) = ed25519::keypair(&[9; 32]);
+        let spoofed_signature =
+            hex::encode(&ed25519::signature(&hex::decode(&challenge).unwrap(), &spoofed)[..]);
+
+        assert_eq!(
+            paired.verify(Some("axon-01"), &challenge, Some(&signature)),
+            Ok(())
+        );
+        assert_eq!(
+            paired.verify(Some("axon-01"), &challenge, Some(&spoofed_signature)),
+            Err(AuthError::BadSignature(String::from("axon-01")))
+        );
+        // truncated input from the device must be refused, not panic.
+        assert_eq!(
+            paired.verify(Some("axon-01"), &challenge, Some(&signature[..40])),
+            Err(AuthError::BadSignature(String::from("axon-01")))
+        );
+        assert_eq!(
+            PairedDevices::check_signature(
+                "axon-01",
+                &hex::encode(&public[..16]),
+                &challenge,
+                Some(&signature)
+            ),
+            Err(AuthError::BadSignature(String::from("axon-01")))
+        );
+        assert_eq!(
+            paired.verify(Some("axon-02"), &challenge, Some(&signature)),
+            Err(AuthError::UnknownDevice(String::from("axon-02")))
+        );
+        assert_eq!(
+            paired.verify(None, &challenge, None),
+            Err(AuthError::Anonymous)
+        );
+        assert!(PairedDevices::unpair(&path, "axon-01").unwrap());
+        assert_eq!(
+            PairedDevices::load(&path)
+                .unwrap()
+                .verify(None, &challenge, None),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,159 @@
+pub mod query {
+
+    use crate::axonmessage::axonmessage::{AxonMessage, Envelope, Frame, Sendable};
+    use crate::command::command::{Command, Operation};
+    use crate::handshake::handshake::{AxonMessageStatus, AxonMessageType, Handshake};
+    use crate::serial::serial_handler::SerialData;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serialport::prelude::*;
+    use std::borrow::BorrowMut;
+    use std::io::{Error, ErrorKind};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    pub const QUERY_PREFIX_BYTE: char = 'Q';
+    // lines read while waiting for the matching response before giving up.
+    const MAX_RESPONSE_LINES: usize = 32;
+
+    static NEXT_CORRELATION_ID: AtomicU32 = AtomicU32::new(1);
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Query {
+        correlation_id: u32,
+        command: Command,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+    #[serde(tag = "type", content = "value", rename_all = "camelCase")]
+    pub enum QueryValue {
+        Number(f64),
+        Text(String),
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct QueryResponse {
+        pub correlation_id: u32,
+        pub result: QueryValue,
+    }
+
+    impl AxonMessage for Query {
+        fn message_type() -> &'static str {
+            "query"
+        }
+    }
+
+    impl Sendable for Query {}
+
+    impl AxonMessage for QueryResponse {
+        fn message_type() -> &'static str {
+            "queryResponse"
+        }
+    }
+
+    impl Query {
+        pub fn new(operation: &Operation) -> Result<Query, Error> {
+            Ok(Query {
+                correlation_id: NEXT_CORRELATION_ID.fetch_add(1, Ordering::SeqCst),
+                command: Command::new(operation)?,
+            })
+        }
+
+        pub fn correlation_id(&self) -> u32 {
+            self.correlation_id
+        }
+
+        pub fn query(
+            path: String,
+            settings: SerialPortSettings,
+            operation: Operation,
+        ) -> Result<QueryValue, Error> {
+            let query = Query::new(&operation)?;
+            let mut port = SerialData::open_port(settings, &path)?;
+            Self::query_port(port.borrow_mut(), &query)
+        }
+
+        pub fn query_port(
+            port: &mut Box<dyn SerialPort>,
+            query: &Query,
+        ) -> Result<QueryValue, Error> {
+            match Handshake::send::<Query>(
+                port,
+                query,
+                QUERY_PREFIX_BYTE,
+                AxonMessageType::QueryMessage,
+            )? {
+                AxonMessageStatus::Success => Self::await_response(port, query.correlation_id),
+                AxonMessageStatus::Failure => Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    "device did not accept the query",
+                )),
+            }
+        }
+
+        // responses to other queries and device logs can be interleaved with ours,
+        // anything that isn't our correlation id is skipped.
+        fn await_response(
+            port: &mut Box<dyn SerialPort>,
+            correlation_id: u32,
+        ) -> Result<QueryValue, Error> {
+            for _ in 0..MAX_RESPONSE_LINES {
+                if let Frame::Envelope(envelope) = Handshake::read_frame(port)? {
+                    if let Some(value) = Self::match_response(envelope, correlation_id)? {
+                        return Ok(value);
+                    }
+                }
+            }
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("no response for query {}", correlation_id),
+            ))
+        }
+
+        pub fn match_response(
+            envelope: Envelope,
+            correlation_id: u32,
+        ) -> Result<Option<QueryValue>, Error> {
+            if !envelope.is::<QueryResponse>() {
+                return Ok(None);
+            }
+            let response = envelope.open::<QueryResponse>()?;
+            if response.correlation_id == correlation_id {
+                Ok(Some(response.result))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axonmessage::axonmessage::{Envelope, Frame};
+    use crate::query::query::{Query, QueryValue};
+
+    fn envelope(data: &str) -> Envelope {
+        match Envelope::decode(data).unwrap() {
+            Frame::Envelope(envelope) => envelope,
+            Frame::Log(_) => panic!("frame decoded as a log line"),
+        }
+    }
+
+    #[test]
+    fn match_response() {
+        let number = "{\"type\":\"queryResponse\",\"payload\":{\"correlationId\":7,\"result\":{\"type\":\"number\",\"value\":512}}}";
+        let text = "{\"type\":\"queryResponse\",\"payload\":{\"correlationId\":8,\"result\":{\"type\":\"text\",\"value\":\"OPEN\"}}}";
+        assert_eq!(
+            Query::match_response(envelope(number), 7).unwrap(),
+            Some(QueryValue::Number(512.0))
+        );
+        assert_eq!(
+            Query::match_response(envelope(text), 8).unwrap(),
+            Some(QueryValue::Text(String::from("OPEN")))
+        );
+        assert_eq!(Query::match_response(envelope(number), 8).unwrap(), None);
+        let record = "{\"type\":\"record\",\"payload\":{}}";
+        assert_eq!(Query::match_response(envelope(record), 7).unwrap(), None);
+    }
+}
@@ -1,22 +1,71 @@
 pub mod record {
 
-    use crate::axonmessage::axonmessage::AxonMessage;
+    use crate::axonmessage::axonmessage::{AxonMessage, Message};
     use crate::handshake::handshake::{AxonMessageType, Handshake};
+    use crate::schema::message_schema::integer_enum;
     use crate::serial::serial_handler::SerialData;
+    use crate::timesync::time_sync::TimeSync;
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::Schema;
+    use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
     use serde_repr::*;
     use serialport::prelude::*;
     use std::borrow::BorrowMut;
-    use std::io::Error;
+    use std::io::{Error, ErrorKind};
 
-    #[derive(Serialize_repr, Deserialize_repr, Debug)]
+    #[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy)]
     #[repr(i8)]
-    enum RecordType {
+    pub enum RecordType {
         Simple = 83,
         Multi = 78,
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    impl RecordType {
+        pub const ALL: [RecordType; 2] = [RecordType::Simple, RecordType::Multi];
+    }
+
+    impl JsonSchema for RecordType {
+        fn schema_name() -> String {
+            String::from("RecordType")
+        }
+
+        fn json_schema(_: &mut SchemaGenerator) -> Schema {
+            integer_enum(RecordType::ALL.iter().map(|kind| *kind as i64))
+        }
+    }
+
+    // more than this in one record is firmware gone wrong, not a busy sensor.
+    pub const MAX_READINGS: usize = 32;
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub enum Quality {
+        #[default]
+        Good,
+        Uncertain,
+        Bad,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Reading {
+        pub sensor: String,
+        pub value: f64,
+        #[serde(default)]
+        pub unit: String,
+        // the device's own clock, usually milliseconds since it booted.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timestamp: Option<u64>,
+        #[serde(default)]
+        pub quality: Quality,
+        // filled in by the host, milliseconds since the epoch.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(skip)]
+        pub wall_time: Option<u64>,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug)]
     #[serde(rename_all = "camelCase")]
     pub struct Record {
         node: String,
@@ -25,21 +74,239 @@ pub mod record {
         record_type: RecordType,
         device_id: String,
         sensor_name: String,
-        encrypted: bool
+        encrypted: bool,
+        // newer firmware sends its readings structured, `data` stays for
+        // everything else.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        readings: Option<Vec<Reading>>,
+        // when the host read the record, milliseconds since the epoch.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(skip)]
+        received_at: Option<u64>,
+    }
+
+    fn invalid(message: String) -> Error {
+        Error::new(ErrorKind::InvalidData, message)
+    }
+
+    impl Quality {
+        pub fn name(&self) -> &'static str {
+            match *self {
+                Quality::Good => "good",
+                Quality::Uncertain => "uncertain",
+                Quality::Bad => "bad",
+            }
+        }
+    }
+
+    impl Reading {
+        pub fn validate(&self) -> Result<(), Error> {
+            if self.sensor.trim().is_empty() {
+                return Err(invalid(String::from("reading has no sensor name")));
+            }
+            if !self.value.is_finite() {
+                return Err(invalid(format!(
+                    "reading from {} is not a finite number",
+                    self.sensor
+                )));
+            }
+            Ok(())
+        }
+    }
+
+    impl AxonMessage for Record {
+        fn message_type() -> &'static str {
+            "record"
+        }
     }
 
-    impl AxonMessage for Record {}
     impl Record {
+        // the structured readings when the device sent them, otherwise `data`
+        // read as a single number from `sensor_name`. records whose data is
+        // anything else have no readings.
+        pub fn readings(&self) -> Result<Vec<Reading>, Error> {
+            if let Some(ref readings) = self.readings {
+                if readings.len() > MAX_READINGS {
+                    return Err(invalid(format!(
+                        "record carries {} readings, at most {} are allowed",
+                        readings.len(),
+                        MAX_READINGS
+                    )));
+                }
+                for reading in readings {
+                    reading.validate()?;
+                }
+                return Ok(readings.clone());
+            }
+            match self.data.trim().parse::<f64>() {
+                Ok(value) if value.is_finite() && !self.sensor_name.is_empty() => {
+                    Ok(vec![Reading {
+                        sensor: self.sensor_name.clone(),
+                        value,
+                        unit: String::new(),
+                        timestamp: None,
+                        quality: Quality::Good,
+                        wall_time: self.received_at,
+                    }])
+                }
+                _ => Ok(Vec::new()),
+            }
+        }
+
+        // writes `readings()` back into the record so whoever gets it sees the
+        // readings next to `deviceId` and `receivedAt`, for plain records too.
+        pub fn fill_readings(&mut self) -> Result<(), Error> {
+            self.readings = Some(self.readings()?);
+            Ok(())
+        }
+
+        pub fn device_id(&self) -> &str {
+            &self.device_id
+        }
+
+        pub fn sensor_name(&self) -> &str {
+            &self.sensor_name
+        }
+
+        pub fn record_type(&self) -> RecordType {
+            self.record_type
+        }
+
+        pub fn data(&self) -> &str {
+            &self.data
+        }
+
+        pub fn has_sensor(&self, sensor: &str) -> bool {
+            self.sensor_name == sensor
+                || self
+                    .readings
+                    .iter()
+                    .flatten()
+                    .any(|reading| reading.sensor == sensor)
+        }
+
+        pub fn received_at(&self) -> Option<u64> {
+            self.received_at
+        }
+
+        // device timestamps only become wall clock times once the port's clock
+        // has been synced, readings without one take the receive time.
+        pub fn stamp(&mut self, port_name: &str, received_at: u64) {
+            self.received_at = Some(received_at);
+            if let Some(ref mut readings) = self.readings {
+                for reading in readings.iter_mut() {
+                    reading.wall_time = match reading.timestamp {
+                        Some(timestamp) => TimeSync::to_wall_clock(port_name, timestamp),
+                        None => Some(received_at),
+                    };
+                }
+            }
+        }
+
         pub fn watch(path: &String, settings: SerialPortSettings) -> Result<Record, Error> {
             let mut port = SerialData::open_port(settings, &path)?;
-            match Handshake::recieve::<Record>(port.borrow_mut(), AxonMessageType::RecordMessage) {
-                Ok(response) => {
-                    let record: Record = serde_json::from_str(&response.to_json_string()?)?;
-                    println!("Some record {:?}", record);
+            Self::watch_port(port.borrow_mut())
+        }
+
+        // storing the record and running rules on it is up to the live callers,
+        // a replayed capture must neither end up in the history nor send commands.
+        pub fn watch_port(port: &mut Box<dyn SerialPort>) -> Result<Record, Error> {
+            match Handshake::recieve::<Record>(port, AxonMessageType::RecordMessage) {
+                Ok(Message::AxonMessage(mut record)) => {
+                    record.stamp(&port.name().unwrap_or_default(), TimeSync::now());
+                    debug!(target: "axon::record", "received record {:?}", record);
+                    record.readings()?;
                     Ok(record)
                 }
+                Ok(Message::Log { data, .. }) => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected a record, got a log line: {}", data),
+                )),
+                Ok(Message::Empty) => Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "expected a record, got an empty handshake",
+                )),
                 Err(e) => Err(e),
             }
         }
     }
 }
+
+// a plain record as a device sends it, for the tests that need one.
+#[cfg(test)]
+pub mod fixture {
+    use crate::record::record::Record;
+    use serde_json::{json, Value};
+
+    pub fn record_json(device_id: &str, sensor_name: &str, data: &str) -> Value {
+        json!({
+            "node": "localhost",
+            "recipient": "TB",
+            "data": data,
+            "recordType": 83,
+            "deviceId": device_id,
+            "sensorName": sensor_name,
+            "encrypted": false
+        })
+    }
+
+    pub fn record(device_id: &str, sensor_name: &str, data: &str) -> Record {
+        serde_json::from_value(record_json(device_id, sensor_name, data)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::record::fixture::record_json;
+    use crate::record::record::{Quality, Record};
+    use serde_json::json;
+
+    #[test]
+    fn structured_and_plain_readings() {
+        let mut record = record_json("device", "temperature", " 21.5 ");
+        let mut plain: Record = serde_json::from_value(record.clone()).unwrap();
+        let readings = plain.readings().unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].sensor, "temperature");
+        assert_eq!(readings[0].value, 21.5);
+        assert!(serde_json::to_value(&plain)
+            .unwrap()
+            .get("readings")
+            .is_none());
+        plain.fill_readings().unwrap();
+        assert_eq!(
+            serde_json::to_value(&plain).unwrap()["readings"][0]["value"],
+            21.5
+        );
+
+        record["data"] = json!("door open");
+        let text: Record = serde_json::from_value(record.clone()).unwrap();
+        assert!(text.readings().unwrap().is_empty());
+
+        record["recordType"] = json!(78);
+        record["readings"] = json!([
+            { "sensor": "temperature", "value": 21.5, "unit": "C", "timestamp": 10233 },
+            { "sensor": "humidity", "value": 40, "unit": "%", "quality": "uncertain" }
+        ]);
+        let structured: Record = serde_json::from_value(record.clone()).unwrap();
+        let readings = structured.readings().unwrap();
+        assert_eq!(readings[0].timestamp, Some(10233));
+        assert_eq!(readings[0].quality, Quality::Good);
+        assert_eq!(readings[1].quality, Quality::Uncertain);
+        assert_eq!(readings[1].value, 40.0);
+
+        record["readings"][1]["sensor"] = json!("");
+        let nameless: Record = serde_json::from_value(record.clone()).unwrap();
+        assert!(nameless.readings().is_err());
+
+        // the port has never been synced, so only the undated reading gets a
+        // wall clock time.
+        record["readings"][1]["sensor"] = json!("humidity");
+        let mut stamped: Record = serde_json::from_value(record).unwrap();
+        stamped.stamp("unsynced", 1_700_000_000_000);
+        let readings = stamped.readings().unwrap();
+        assert_eq!(stamped.received_at(), Some(1_700_000_000_000));
+        assert_eq!(readings[0].wall_time, None);
+        assert_eq!(readings[1].wall_time, Some(1_700_000_000_000));
+    }
+}
@@ -0,0 +1,212 @@
+pub mod message_registry {
+
+    use crate::axonmessage::axonmessage::{Envelope, Message, BUILTIN_MESSAGE_TYPES};
+    use crate::handshake::handshake::{AxonMessageStatus, AxonMessageType, Handshake};
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use serialport::prelude::*;
+    use std::collections::BTreeMap;
+    use std::io::{Error, ErrorKind};
+    use std::sync::{Arc, Mutex};
+
+    // ids below this are kept for the crate's own message types.
+    pub const MIN_CUSTOM_ID: i8 = 64;
+    const RESERVED_PREFIXES: [char; 5] = ['H', 'C', 'Q', 'S', 'T'];
+
+    static REGISTRY: Mutex<BTreeMap<String, Registration>> = Mutex::new(BTreeMap::new());
+
+    // takes the payload of a received frame, and optionally returns a
+    // replacement for it.
+    pub type MessageHandler = Arc<dyn Fn(&Value) -> Result<Option<Value>, Error> + Send + Sync>;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct MessageDefinition {
+        pub id: i8,
+        pub name: String,
+        pub prefix: char,
+    }
+
+    struct Registration {
+        definition: MessageDefinition,
+        handler: Option<MessageHandler>,
+    }
+
+    fn invalid(message: String) -> Error {
+        Error::new(ErrorKind::InvalidInput, message)
+    }
+
+    pub struct MessageRegistry;
+
+    impl MessageRegistry {
+        // registering a name again replaces its definition and handler.
+        pub fn register(
+            definition: MessageDefinition,
+            handler: Option<MessageHandler>,
+        ) -> Result<(), Error> {
+            if definition.id < MIN_CUSTOM_ID {
+                return Err(invalid(format!(
+                    "message type id {} is reserved, use {} or above",
+                    definition.id, MIN_CUSTOM_ID
+                )));
+            }
+            if BUILTIN_MESSAGE_TYPES.contains(&definition.name.as_str()) {
+                return Err(invalid(format!(
+                    "message type `{}` is built in",
+                    definition.name
+                )));
+            }
+            if !definition.prefix.is_ascii_graphic()
+                || RESERVED_PREFIXES.contains(&definition.prefix)
+            {
+                return Err(invalid(format!(
+                    "prefix `{}` is reserved or not printable",
+                    definition.prefix
+                )));
+            }
+            let mut registry = REGISTRY.lock().unwrap();
+            let conflict = registry.values().find(|registered| {
+                registered.definition.name != definition.name
+                    && (registered.definition.id == definition.id
+                        || registered.definition.prefix == definition.prefix)
+            });
+            if let Some(registered) = conflict {
+                return Err(invalid(format!(
+                    "message type `{}` already uses id {} or prefix `{}`",
+                    registered.definition.name, definition.id, definition.prefix
+                )));
+            }
+            registry.insert(
+                definition.name.clone(),
+                Registration {
+                    definition,
+                    handler,
+                },
+            );
+            Ok(())
+        }
+
+        pub fn register_json(definition: &str) -> Result<MessageDefinition, Error> {
+            let definition: MessageDefinition = serde_json::from_str(definition)?;
+            Self::register(definition.clone(), None)?;
+            Ok(definition)
+        }
+
+        pub fn unregister(name: &str) -> bool {
+            REGISTRY.lock().unwrap().remove(name).is_some()
+        }
+
+        pub fn is_registered(name: &str) -> bool {
+            REGISTRY.lock().unwrap().contains_key(name)
+        }
+
+        pub fn definition(name: &str) -> Result<MessageDefinition, Error> {
+            REGISTRY
+                .lock()
+                .unwrap()
+                .get(name)
+                .map(|registered| registered.definition.clone())
+                .ok_or_else(|| invalid(format!("message type `{}` is not registered", name)))
+        }
+
+        pub fn definitions() -> Vec<MessageDefinition> {
+            REGISTRY
+                .lock()
+                .unwrap()
+                .values()
+                .map(|registered| registered.definition.clone())
+                .collect()
+        }
+
+        fn handler(name: &str) -> Option<MessageHandler> {
+            REGISTRY
+                .lock()
+                .unwrap()
+                .get(name)
+                .and_then(|registered| registered.handler.clone())
+        }
+
+        pub fn send(
+            port: &mut Box<dyn SerialPort>,
+            name: &str,
+            payload: Value,
+        ) -> Result<AxonMessageStatus, Error> {
+            let definition = Self::definition(name)?;
+            Handshake::send_envelope(
+                port,
+                Envelope::new(&definition.name, payload),
+                definition.prefix,
+                AxonMessageType::Custom(definition.id),
+            )
+        }
+
+        pub fn recieve(
+            port: &mut Box<dyn SerialPort>,
+            name: &str,
+        ) -> Result<Message<Value>, Error> {
+            let definition = Self::definition(name)?;
+            let envelope =
+                match Handshake::recieve_envelope(port, AxonMessageType::Custom(definition.id))? {
+                    Message::AxonMessage(envelope) => envelope,
+                    Message::Empty => return Ok(Message::Empty),
+                    Message::Log { status, data } => return Ok(Message::Log { status, data }),
+                };
+            if envelope.message_type != definition.name {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "expected a `{}` frame, got `{}`",
+                        definition.name, envelope.message_type
+                    ),
+                ));
+            }
+            let payload = match Self::handler(name) {
+                Some(handler) => handler(&envelope.payload)?.unwrap_or(envelope.payload),
+                None => envelope.payload,
+            };
+            Ok(Message::AxonMessage(payload))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handshake::handshake::AxonMessageType;
+    use crate::registry::message_registry::{MessageDefinition, MessageRegistry};
+
+    #[test]
+    fn register_message_types() {
+        let diagnostics =
+            MessageRegistry::register_json("{\"id\":70,\"name\":\"diagnostics\",\"prefix\":\"D\"}")
+                .unwrap();
+        assert!(MessageRegistry::is_registered("diagnostics"));
+        assert_eq!(
+            MessageRegistry::definition("diagnostics").unwrap(),
+            diagnostics
+        );
+
+        let conflicting = vec![
+            (2, "inventory", 'I'),
+            (71, "record", 'I'),
+            (71, "inventory", 'C'),
+            (70, "inventory", 'I'),
+            (71, "inventory", 'D'),
+        ];
+        for (id, name, prefix) in conflicting {
+            let definition = MessageDefinition {
+                id,
+                name: String::from(name),
+                prefix,
+            };
+            assert!(MessageRegistry::register(definition, None).is_err());
+        }
+
+        let custom: AxonMessageType = serde_json::from_str("70").unwrap();
+        assert_eq!(custom, AxonMessageType::Custom(70));
+        assert_eq!(serde_json::to_string(&custom).unwrap(), "70");
+        assert!(serde_json::from_str::<AxonMessageType>("12").is_err());
+
+        assert!(MessageRegistry::unregister("diagnostics"));
+        assert!(!MessageRegistry::is_registered("diagnostics"));
+    }
+}
@@ -0,0 +1,393 @@
+pub mod rule_engine {
+
+    use crate::command::command::{Command, Operation};
+    use crate::record::record::Record;
+    use crate::timesync::time_sync::TimeSync;
+    use serde::{Deserialize, Serialize};
+    use serialport::prelude::*;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fs;
+    use std::io::Result as SingleResult;
+    use std::io::{Error, ErrorKind};
+    use std::sync::Mutex;
+
+    pub const HIT_CAPACITY: usize = 256;
+
+    static ENGINE: Mutex<RuleEngine> = Mutex::new(RuleEngine::new());
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(tag = "kind", rename_all = "camelCase")]
+    pub enum Condition {
+        Above { threshold: f64 },
+        Below { threshold: f64 },
+        Between { min: f64, max: f64 },
+        Outside { min: f64, max: f64 },
+    }
+
+    // fires `operation` once when a reading from `sensor` starts meeting
+    // `condition` and keeps meeting it for `debounce_millis`. the rule re-arms
+    // when the condition stops holding.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Rule {
+        pub name: String,
+        pub sensor: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub device_id: Option<String>,
+        pub condition: Condition,
+        #[serde(default)]
+        pub debounce_millis: u64,
+        pub operation: Operation,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RuleHit {
+        pub id: u64,
+        pub rule: String,
+        pub device_id: String,
+        pub sensor: String,
+        pub value: f64,
+        pub at: u64,
+        pub operation: Operation,
+        // hits are only kept once their command has been sent.
+        pub status: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct RuleFile {
+        rules: Vec<Rule>,
+    }
+
+    #[derive(Default)]
+    struct RuleState {
+        since: Option<u64>,
+        fired: bool,
+    }
+
+    pub struct RuleEngine {
+        rules: Vec<Rule>,
+        // keyed by rule name and device id, so one rule can watch many devices.
+        states: BTreeMap<(String, String), RuleState>,
+        hits: VecDeque<RuleHit>,
+        next_id: u64,
+    }
+
+    fn invalid(message: String) -> Error {
+        Error::new(ErrorKind::InvalidInput, message)
+    }
+
+    impl Condition {
+        pub fn holds(&self, value: f64) -> bool {
+            match *self {
+                Condition::Above { threshold } => value > threshold,
+                Condition::Below { threshold } => value < threshold,
+                Condition::Between { min, max } => value >= min && value <= max,
+                Condition::Outside { min, max } => value < min || value > max,
+            }
+        }
+    }
+
+    impl Rule {
+        pub fn validate(&self) -> SingleResult<()> {
+            if self.name.trim().is_empty() {
+                return Err(invalid(String::from("rule has no name")));
+            }
+            if self.sensor.trim().is_empty() {
+                return Err(invalid(format!("rule {} has no sensor", self.name)));
+            }
+            match self.condition {
+                Condition::Between { min, max } | Condition::Outside { min, max } if min > max => {
+                    return Err(invalid(format!(
+                        "rule {} has min {} above max {}",
+                        self.name, min, max
+                    )))
+                }
+                _ => (),
+            }
+            self.operation
+                .validate()
+                .map_err(|e| invalid(format!("rule {}: {}", self.name, e)))
+        }
+    }
+
+    impl RuleState {
+        // true exactly once per stretch of the condition holding.
+        fn observe(&mut self, holds: bool, now: u64, debounce_millis: u64) -> bool {
+            if !holds {
+                *self = RuleState::default();
+                return false;
+            }
+            let since = *self.since.get_or_insert(now);
+            if self.fired || now.saturating_sub(since) < debounce_millis {
+                return false;
+            }
+            self.fired = true;
+            true
+        }
+    }
+
+    impl RuleEngine {
+        pub const fn new() -> RuleEngine {
+            RuleEngine {
+                rules: Vec::new(),
+                states: BTreeMap::new(),
+                hits: VecDeque::new(),
+                next_id: 1,
+            }
+        }
+
+        // a JSON array of rules, every one checked before any is used.
+        pub fn parse(data: &str) -> SingleResult<Vec<Rule>> {
+            Self::checked(serde_json::from_str(data)?)
+        }
+
+        // TOML has no top level arrays, so the rules sit in `[[rules]]` tables.
+        pub fn parse_toml(data: &str) -> SingleResult<Vec<Rule>> {
+            let file: RuleFile =
+                toml::from_str(data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            Self::checked(file.rules)
+        }
+
+        // `.toml` files are read as TOML, anything else as JSON.
+        pub fn parse_file(path: &str) -> SingleResult<Vec<Rule>> {
+            let data = fs::read_to_string(path)?;
+            if path.ends_with(".toml") {
+                Self::parse_toml(&data)
+            } else {
+                Self::parse(&data)
+            }
+        }
+
+        fn checked(rules: Vec<Rule>) -> SingleResult<Vec<Rule>> {
+            for (i, rule) in rules.iter().enumerate() {
+                rule.validate()?;
+                if rules[..i].iter().any(|other| other.name == rule.name) {
+                    return Err(invalid(format!("rule {} is defined twice", rule.name)));
+                }
+            }
+            Ok(rules)
+        }
+
+        pub fn set_rules(&mut self, rules: Vec<Rule>) {
+            self.rules = rules;
+            self.states.clear();
+        }
+
+        pub fn rules(&self) -> &Vec<Rule> {
+            &self.rules
+        }
+
+        // the rules that fire for `record`, without sending anything.
+        pub fn evaluate(&mut self, record: &Record, now: u64) -> Vec<RuleHit> {
+            let readings = record.readings().unwrap_or_default();
+            let mut hits = Vec::new();
+            for rule in self.rules.iter() {
+                if rule
+                    .device_id
+                    .as_ref()
+                    .is_some_and(|device_id| device_id != record.device_id())
+                {
+                    continue;
+                }
+                for reading in readings
+                    .iter()
+                    .filter(|reading| reading.sensor == rule.sensor)
+                {
+                    let state = self
+                        .states
+                        .entry((rule.name.clone(), record.device_id().to_string()))
+                        .or_default();
+                    let holds = rule.condition.holds(reading.value);
+                    if state.observe(holds, now, rule.debounce_millis) {
+                        hits.push(RuleHit {
+                            id: 0,
+                            rule: rule.name.clone(),
+                            device_id: record.device_id().to_string(),
+                            sensor: reading.sensor.clone(),
+                            value: reading.value,
+                            at: now,
+                            operation: rule.operation.clone(),
+                            status: false,
+                            error: None,
+                        });
+                    }
+                }
+            }
+            hits
+        }
+
+        // a hit whose command didn't go out fires again on the next record that
+        // still meets the condition, the debounce isn't started over.
+        pub fn rearm(&mut self, hit: &RuleHit) {
+            if let Some(state) = self
+                .states
+                .get_mut(&(hit.rule.clone(), hit.device_id.clone()))
+            {
+                state.fired = false;
+            }
+        }
+
+        fn push_hit(&mut self, mut hit: RuleHit) -> RuleHit {
+            hit.id = self.next_id;
+            self.next_id += 1;
+            if self.hits.len() == HIT_CAPACITY {
+                self.hits.pop_front();
+            }
+            self.hits.push_back(hit.clone());
+            hit
+        }
+    }
+
+    impl Default for RuleEngine {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub struct Rules;
+
+    impl Rules {
+        pub fn load(path: &str) -> SingleResult<usize> {
+            let rules = RuleEngine::parse_file(path)?;
+            let count = rules.len();
+            Self::set(rules);
+            info!(target: "axon::rule", "loaded {} rules from {}", count, path);
+            Ok(count)
+        }
+
+        pub fn set(rules: Vec<Rule>) {
+            ENGINE.lock().unwrap().set_rules(rules);
+        }
+
+        pub fn list() -> Vec<Rule> {
+            ENGINE.lock().unwrap().rules().clone()
+        }
+
+        // the rules `record` fires, with nothing sent yet.
+        pub fn evaluate(record: &Record) -> Vec<RuleHit> {
+            let now = record.received_at().unwrap_or_else(TimeSync::now);
+            ENGINE.lock().unwrap().evaluate(record, now)
+        }
+
+        // sends the command of every hit on the port the record came in on. a
+        // failed send is kept on the hit, it never fails the read.
+        pub fn fire(port: &mut Box<dyn SerialPort>, hits: Vec<RuleHit>) -> Vec<RuleHit> {
+            hits.into_iter()
+                .map(|mut hit| {
+                    let sent = Command::new(&hit.operation)
+                        .and_then(|command| Command::send_command_port(port, &command));
+                    let mut engine = ENGINE.lock().unwrap();
+                    match sent {
+                        Ok(response) => hit.status = response.status,
+                        Err(e) => {
+                            hit.error = Some(e.to_string());
+                            engine.rearm(&hit);
+                        }
+                    }
+                    let hit = engine.push_hit(hit);
+                    drop(engine);
+                    info!(
+                        target: "axon::rule",
+                        "rule {} fired on {} from {} = {}: {} {}",
+                        hit.rule,
+                        hit.device_id,
+                        hit.sensor,
+                        hit.value,
+                        hit.operation.name(),
+                        if hit.status { "ok" } else { "failed" }
+                    );
+                    hit
+                })
+                .collect()
+        }
+
+        pub fn apply(port: &mut Box<dyn SerialPort>, record: &Record) -> Vec<RuleHit> {
+            Self::fire(port, Self::evaluate(record))
+        }
+
+        pub fn hits_since(cursor: u64) -> Vec<RuleHit> {
+            ENGINE
+                .lock()
+                .unwrap()
+                .hits
+                .iter()
+                .filter(|hit| hit.id > cursor)
+                .cloned()
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::record::fixture::record;
+    use crate::record::record::Record;
+    use crate::rules::rule_engine::RuleEngine;
+    use serde_json::json;
+
+    fn temperature(value: &str) -> Record {
+        record("greenhouse", "temperature", value)
+    }
+
+    #[test]
+    fn thresholds_and_debounce() {
+        let rules = RuleEngine::parse(
+            &json!([{
+                "name": "fan on",
+                "sensor": "temperature",
+                "condition": { "kind": "above", "threshold": 30 },
+                "debounceMillis": 1000,
+                "operation": { "op": "digitalWrite", "pin": 7, "value": true }
+            }])
+            .to_string(),
+        )
+        .unwrap();
+        let mut engine = RuleEngine::new();
+        engine.set_rules(rules);
+
+        assert!(engine.evaluate(&temperature("31"), 0).is_empty());
+        assert!(engine.evaluate(&temperature("32"), 500).is_empty());
+        let hits = engine.evaluate(&temperature("33"), 1000);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rule, "fan on");
+        assert_eq!(hits[0].value, 33.0);
+        // still hot, but it already fired.
+        assert!(engine.evaluate(&temperature("34"), 5000).is_empty());
+        // cooling down re-arms it, and the debounce starts over.
+        assert!(engine.evaluate(&temperature("25"), 6000).is_empty());
+        assert!(engine.evaluate(&temperature("31"), 7000).is_empty());
+        assert!(engine
+            .evaluate(&temperature("not a number"), 7500)
+            .is_empty());
+        let hits = engine.evaluate(&temperature("31"), 8000);
+        assert_eq!(hits.len(), 1);
+        // a command that didn't go out is retried on the next hot reading.
+        engine.rearm(&hits[0]);
+        assert_eq!(engine.evaluate(&temperature("31"), 8100).len(), 1);
+        assert!(engine.evaluate(&temperature("31"), 8200).is_empty());
+
+        let from_toml = RuleEngine::parse_toml(
+            r#"
+            [[rules]]
+            name = "fan on"
+            sensor = "temperature"
+            condition = { kind = "above", threshold = 30 }
+            debounceMillis = 1000
+            operation = { op = "digitalWrite", pin = 7, value = true }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(from_toml, engine.rules().clone());
+
+        let invalid = json!([{
+            "name": "bad range",
+            "sensor": "temperature",
+            "condition": { "kind": "between", "min": 10, "max": 5 },
+            "operation": { "op": "digitalWrite", "pin": 7, "value": false }
+        }]);
+        assert!(RuleEngine::parse(&invalid.to_string()).is_err());
+    }
+}
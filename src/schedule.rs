@@ -0,0 +1,592 @@
+pub mod command_schedule {
+
+    use crate::command::command::{Command, Operation};
+    use crate::serial::serial_handler::DEFAULT_SETTINGS;
+    use crate::supervisor::device_supervisor::DeviceSupervisor;
+    use crate::timesync::time_sync::TimeSync;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::fs::{File, OpenOptions};
+    use std::io::prelude::*;
+    use std::io::Result as SingleResult;
+    use std::io::{Error, ErrorKind};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    const MINUTE_MILLIS: u64 = 60 * 1000;
+    const DAY_MILLIS: u64 = 24 * 60 * MINUTE_MILLIS;
+    const MIN_INTERVAL_MILLIS: u64 = 1000;
+    const TICK: Duration = Duration::from_millis(1000);
+
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+    // bumped by `stop`, so a loop still asleep from before a quick restart
+    // doesn't keep running next to the new one.
+    static GENERATION: AtomicU32 = AtomicU32::new(0);
+    // schedules whose command is still out, they aren't started again until it
+    // comes back.
+    static IN_FLIGHT: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(tag = "kind", rename_all = "camelCase")]
+    pub enum Trigger {
+        #[serde(rename_all = "camelCase")]
+        Interval {
+            every_millis: u64,
+        },
+        // five field cron, `minute hour day-of-month month day-of-week`, in UTC.
+        Cron {
+            expression: String,
+        },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Schedule {
+        pub name: String,
+        pub path: String,
+        pub trigger: Trigger,
+        pub operation: Operation,
+        #[serde(default = "Schedule::enabled")]
+        pub enabled: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_run: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_status: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_error: Option<String>,
+        #[serde(default)]
+        pub runs: u64,
+        #[serde(default)]
+        pub failures: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default)]
+    pub struct Schedules {
+        schedules: Vec<Schedule>,
+    }
+
+    // one bit per allowed value of each field.
+    #[derive(Debug, PartialEq)]
+    pub struct Cron {
+        minutes: u64,
+        hours: u64,
+        days_of_month: u64,
+        months: u64,
+        days_of_week: u64,
+        any_day_of_month: bool,
+        any_day_of_week: bool,
+    }
+
+    fn invalid(message: String) -> Error {
+        Error::new(ErrorKind::InvalidInput, message)
+    }
+
+    // `*`, `5`, `1-5`, `*/15` or `0-30/10`, comma separated.
+    fn cron_field(field: &str, min: u64, max: u64) -> SingleResult<u64> {
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|value| *value >= min && *value <= max)
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "`{}` in `{}` is not between {} and {}",
+                        value, field, min, max
+                    ))
+                })
+        };
+        let mut bits = 0;
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => match step.parse::<u64>() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => return Err(invalid(format!("bad step in `{}`", field))),
+                },
+                None => (item, 1),
+            };
+            let (first, last) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((first, last)) => (number(first)?, number(last)?),
+                None => (number(range)?, number(range)?),
+            };
+            if first > last {
+                return Err(invalid(format!("`{}` runs backwards", item)));
+            }
+            for value in (first..=last).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(bits)
+    }
+
+    // the UTC calendar date of a day counted from the epoch, as (month, day).
+    fn month_and_day(days: u64) -> (u64, u64) {
+        let z = days + 719_468;
+        let day_of_era = z % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        (month, day)
+    }
+
+    impl Cron {
+        pub fn parse(expression: &str) -> SingleResult<Cron> {
+            let fields: Vec<&str> = expression.split_whitespace().collect();
+            if fields.len() != 5 {
+                return Err(invalid(format!(
+                    "`{}` needs five fields: minute hour day-of-month month day-of-week",
+                    expression
+                )));
+            }
+            let days_of_week = cron_field(fields[4], 0, 7)?;
+            Ok(Cron {
+                minutes: cron_field(fields[0], 0, 59)?,
+                hours: cron_field(fields[1], 0, 23)?,
+                days_of_month: cron_field(fields[2], 1, 31)?,
+                months: cron_field(fields[3], 1, 12)?,
+                // 7 is another name for sunday.
+                days_of_week: (days_of_week | days_of_week >> 7) & 0x7f,
+                any_day_of_month: fields[2] == "*",
+                any_day_of_week: fields[4] == "*",
+            })
+        }
+
+        pub fn matches(&self, millis: u64) -> bool {
+            let days = millis / DAY_MILLIS;
+            let minute_of_day = millis % DAY_MILLIS / MINUTE_MILLIS;
+            let (month, day) = month_and_day(days);
+            // the epoch was a thursday.
+            let weekday = (days + 4) % 7;
+            let day_of_month = self.days_of_month & 1 << day != 0;
+            let day_of_week = self.days_of_week & 1 << weekday != 0;
+            // like cron, when both day fields are restricted either one will do.
+            let day_matches = match (self.any_day_of_month, self.any_day_of_week) {
+                (false, false) => day_of_month || day_of_week,
+                _ => day_of_month && day_of_week,
+            };
+            self.minutes & 1 << (minute_of_day % 60) != 0
+                && self.hours & 1 << (minute_of_day / 60) != 0
+                && self.months & 1 << month != 0
+                && day_matches
+        }
+    }
+
+    impl Schedule {
+        fn enabled() -> bool {
+            true
+        }
+
+        pub fn validate(&self) -> SingleResult<()> {
+            if self.name.trim().is_empty() {
+                return Err(invalid(String::from("schedule has no name")));
+            }
+            if self.path.trim().is_empty() {
+                return Err(invalid(format!("schedule {} has no port", self.name)));
+            }
+            match self.trigger {
+                Trigger::Interval { every_millis } if every_millis < MIN_INTERVAL_MILLIS => {
+                    return Err(invalid(format!(
+                        "schedule {} runs more often than every {}ms",
+                        self.name, MIN_INTERVAL_MILLIS
+                    )))
+                }
+                Trigger::Interval { .. } => (),
+                Trigger::Cron { ref expression } => {
+                    Cron::parse(expression)?;
+                }
+            }
+            self.operation
+                .validate()
+                .map_err(|e| invalid(format!("schedule {}: {}", self.name, e)))
+        }
+
+        // interval schedules run on the first tick and then every interval, cron
+        // schedules at most once in each matching minute.
+        pub fn is_due(&self, now: u64) -> SingleResult<bool> {
+            if !self.enabled {
+                return Ok(false);
+            }
+            match self.trigger {
+                Trigger::Interval { every_millis } => Ok(self
+                    .last_run
+                    .is_none_or(|last_run| now >= last_run.saturating_add(every_millis))),
+                Trigger::Cron { ref expression } => Ok(Cron::parse(expression)?.matches(now)
+                    && self
+                        .last_run
+                        .is_none_or(|last_run| last_run / MINUTE_MILLIS != now / MINUTE_MILLIS)),
+            }
+        }
+
+        fn record_run(&mut self, at: u64, result: &SingleResult<bool>) {
+            self.last_run = Some(at);
+            self.runs += 1;
+            match result {
+                Ok(status) => {
+                    self.last_status = Some(*status);
+                    self.last_error = None;
+                }
+                Err(e) => {
+                    self.last_status = Some(false);
+                    self.last_error = Some(e.to_string());
+                }
+            }
+            if self.last_status != Some(true) {
+                self.failures += 1;
+            }
+        }
+    }
+
+    impl Schedules {
+        pub fn load(path: &String) -> SingleResult<Schedules> {
+            if !Path::new(path).exists() {
+                return Ok(Schedules::default());
+            }
+            let mut schedules_file = File::open(path)?;
+            let mut schedules_string = String::new();
+            schedules_file.read_to_string(&mut schedules_string)?;
+            Ok(serde_json::from_str(&schedules_string)?)
+        }
+
+        // written next to the file and renamed over it, so a crash mid write
+        // leaves the old schedules rather than half of the new ones.
+        pub fn save(&self, path: &String) -> SingleResult<()> {
+            let temp_path = format!("{}.tmp", path);
+            let mut schedules_file = File::create(&temp_path)?;
+            schedules_file.write_all(serde_json::to_string(&self)?.as_bytes())?;
+            schedules_file.sync_all()?;
+            fs::rename(&temp_path, path)
+        }
+
+        // held for every read-modify-write of the schedules file, so a run being
+        // recorded can't undo an edit made while its command was out. it is an
+        // OS lock on a file next to the schedules, so the CLI and a scheduler in
+        // another process take turns too. released when the file is dropped.
+        fn lock(path: &String) -> SingleResult<File> {
+            let lock_file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(format!("{}.lock", path))?;
+            lock_file.lock()?;
+            Ok(lock_file)
+        }
+
+        pub fn schedules(&self) -> &Vec<Schedule> {
+            &self.schedules
+        }
+
+        pub fn list(path: &String) -> SingleResult<Vec<Schedule>> {
+            let _lock = Self::lock(path)?;
+            Ok(Self::load(path)?.schedules)
+        }
+
+        pub fn add(path: &String, schedule: Schedule) -> SingleResult<()> {
+            schedule.validate()?;
+            let _lock = Self::lock(path)?;
+            let mut schedules = Self::load(path)?;
+            if schedules.schedules.iter().any(|s| s.name == schedule.name) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("schedule {} already exists", schedule.name),
+                ));
+            }
+            schedules.schedules.push(schedule);
+            schedules.save(path)
+        }
+
+        // replaces what to run and when, the run history stays.
+        pub fn update(path: &String, schedule: Schedule) -> SingleResult<()> {
+            schedule.validate()?;
+            let _lock = Self::lock(path)?;
+            let mut schedules = Self::load(path)?;
+            let existing = schedules
+                .schedules
+                .iter_mut()
+                .find(|s| s.name == schedule.name)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("no schedule named {}", schedule.name),
+                    )
+                })?;
+            existing.path = schedule.path;
+            existing.trigger = schedule.trigger;
+            existing.operation = schedule.operation;
+            existing.enabled = schedule.enabled;
+            schedules.save(path)
+        }
+
+        pub fn remove(path: &String, name: &str) -> SingleResult<bool> {
+            let _lock = Self::lock(path)?;
+            let mut schedules = Self::load(path)?;
+            let before = schedules.schedules.len();
+            schedules.schedules.retain(|s| s.name != name);
+            schedules.save(path)?;
+            Ok(schedules.schedules.len() != before)
+        }
+
+        fn record_run(
+            path: &String,
+            name: &str,
+            at: u64,
+            result: &SingleResult<bool>,
+        ) -> SingleResult<()> {
+            let _lock = Self::lock(path)?;
+            let mut schedules = Self::load(path)?;
+            // removed while its command was out.
+            if let Some(schedule) = schedules.schedules.iter_mut().find(|s| s.name == name) {
+                schedule.record_run(at, result);
+                schedules.save(path)?;
+            }
+            Ok(())
+        }
+    }
+
+    pub struct Scheduler;
+
+    impl Scheduler {
+        fn due(path: &String, now: u64) -> SingleResult<Vec<Schedule>> {
+            let mut due = Vec::new();
+            for schedule in Schedules::list(path)? {
+                // a broken schedule, e.g. edited by hand, doesn't stop the others.
+                match schedule.is_due(now) {
+                    Ok(true) => due.push(schedule),
+                    Ok(false) => (),
+                    Err(e) => warn!(
+                        target: "axon::schedule",
+                        "skipping {}: {}",
+                        schedule.name,
+                        e
+                    ),
+                }
+            }
+            Ok(due)
+        }
+
+        fn run_one<F>(path: &String, schedule: &Schedule, now: u64, run: F) -> SingleResult<()>
+        where
+            F: FnOnce(&Schedule) -> SingleResult<bool>,
+        {
+            let result = run(schedule);
+            match result {
+                Ok(true) => info!(target: "axon::schedule", "{} ran", schedule.name),
+                Ok(false) => warn!(
+                    target: "axon::schedule",
+                    "{} was refused by the device", schedule.name
+                ),
+                Err(ref e) => {
+                    warn!(target: "axon::schedule", "{} failed: {}", schedule.name, e)
+                }
+            }
+            Schedules::record_run(path, &schedule.name, now, &result)
+        }
+
+        // runs every schedule due at `now` through `run`, one after the other,
+        // which reports whether the device accepted the command. returns the
+        // names that ran.
+        pub fn tick<F>(path: &String, now: u64, mut run: F) -> SingleResult<Vec<String>>
+        where
+            F: FnMut(&Schedule) -> SingleResult<bool>,
+        {
+            let mut ran = Vec::new();
+            for schedule in Self::due(path, now)? {
+                Self::run_one(path, &schedule, now, &mut run)?;
+                ran.push(schedule.name);
+            }
+            Ok(ran)
+        }
+
+        // like `tick`, but every schedule is sent from its own thread, so a device
+        // that is slow to answer doesn't hold up the others or the next tick.
+        fn spawn_due(path: &String, now: u64) -> SingleResult<()> {
+            for schedule in Self::due(path, now)? {
+                if !IN_FLIGHT.lock().unwrap().insert(schedule.name.clone()) {
+                    continue;
+                }
+                let path = path.clone();
+                thread::spawn(move || {
+                    if let Err(e) = Self::run_one(&path, &schedule, now, Self::send) {
+                        warn!(
+                            target: "axon::schedule",
+                            "could not record run of {}: {}",
+                            schedule.name,
+                            e
+                        );
+                    }
+                    IN_FLIGHT.lock().unwrap().remove(&schedule.name);
+                });
+            }
+            Ok(())
+        }
+
+        // through the supervisor that has the port open when there is one, the
+        // port can't be opened a second time while it holds it.
+        fn send(schedule: &Schedule) -> SingleResult<bool> {
+            let response = match DeviceSupervisor::shared_port(&schedule.path) {
+                Some(port) => {
+                    let command = Command::new(&schedule.operation)?;
+                    port.with(|port| Command::send_command_port(port, &command))?
+                }
+                None => Command::send_command(
+                    schedule.path.clone(),
+                    DEFAULT_SETTINGS,
+                    schedule.operation.clone(),
+                )?,
+            };
+            Ok(response.status)
+        }
+
+        // blocks until `stop`, for the CLI. the schedules file is read on every
+        // tick, so edits take effect without a restart.
+        pub fn run(path: &String) {
+            RUNNING.store(true, Ordering::SeqCst);
+            Self::run_generation(path, GENERATION.load(Ordering::SeqCst));
+        }
+
+        // runs until `stop` moves the generation on or clears RUNNING.
+        fn run_generation(path: &String, generation: u32) {
+            while RUNNING.load(Ordering::SeqCst) && GENERATION.load(Ordering::SeqCst) == generation
+            {
+                if let Err(e) = Self::spawn_due(path, TimeSync::now()) {
+                    warn!(target: "axon::schedule", "could not run schedules: {}", e);
+                }
+                thread::sleep(TICK);
+            }
+        }
+
+        // false when the scheduler was already running.
+        pub fn start(path: String) -> bool {
+            if RUNNING.swap(true, Ordering::SeqCst) {
+                return false;
+            }
+            // read before spawning, a `stop` right after this returns must win.
+            let generation = GENERATION.load(Ordering::SeqCst);
+            thread::spawn(move || Self::run_generation(&path, generation));
+            true
+        }
+
+        pub fn stop() {
+            GENERATION.fetch_add(1, Ordering::SeqCst);
+            RUNNING.store(false, Ordering::SeqCst);
+        }
+
+        pub fn is_running() -> bool {
+            RUNNING.load(Ordering::SeqCst)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schedule::command_schedule::{Cron, Schedule, Scheduler, Schedules};
+    use serde_json::json;
+    use std::env;
+    use std::fs;
+    use std::io::{Error, ErrorKind};
+
+    // 2024-03-15 12:30 UTC, a friday.
+    const FRIDAY_NOON: u64 = 1_710_505_800_000;
+    // 2024-02-29 23:59 UTC, a thursday.
+    const LEAP_DAY: u64 = 1_709_251_140_000;
+
+    #[test]
+    fn cron_expressions() {
+        assert!(Cron::parse("*/15 12 * * *").unwrap().matches(FRIDAY_NOON));
+        assert!(!Cron::parse("*/15 13 * * *").unwrap().matches(FRIDAY_NOON));
+        assert!(Cron::parse("30 9-17 * 3 1-5").unwrap().matches(FRIDAY_NOON));
+        assert!(!Cron::parse("30 12 * * 0,6").unwrap().matches(FRIDAY_NOON));
+        // either day field is enough once both are restricted.
+        assert!(Cron::parse("30 12 1 * 5").unwrap().matches(FRIDAY_NOON));
+        assert!(Cron::parse("59 23 29 2 *").unwrap().matches(LEAP_DAY));
+        assert!(Cron::parse("59 23 * * 4").unwrap().matches(LEAP_DAY));
+        assert!(Cron::parse("0 0 * * 7").unwrap().matches(1_710_633_600_000));
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").is_err());
+        assert!(Cron::parse("* * * *").is_err());
+    }
+
+    #[test]
+    fn schedules_run_and_record() {
+        let mut path = env::temp_dir();
+        path.push("axon-schedules-test.json");
+        let _ = fs::remove_file(&path);
+        let path = path.to_str().unwrap().to_string();
+        let schedule = |name: &str, trigger: serde_json::Value| -> Schedule {
+            serde_json::from_value(json!({
+                "name": name,
+                "path": "/dev/ttyACM0",
+                "trigger": trigger,
+                "operation": { "op": "pulse", "pin": 8, "millis": 500 }
+            }))
+            .unwrap()
+        };
+        let pump = schedule("pump", json!({ "kind": "interval", "everyMillis": 60000 }));
+        let lights = schedule(
+            "lights",
+            json!({ "kind": "cron", "expression": "30 12 * * *" }),
+        );
+        Schedules::add(&path, pump.clone()).unwrap();
+        Schedules::add(&path, lights).unwrap();
+        assert!(Schedules::add(&path, pump).is_err());
+        let fast = schedule("fast", json!({ "kind": "interval", "everyMillis": 10 }));
+        assert!(Schedules::add(&path, fast).is_err());
+        let mut yearly = schedule(
+            "yearly",
+            json!({ "kind": "interval", "everyMillis": u64::MAX }),
+        );
+        yearly.last_run = Some(FRIDAY_NOON);
+        assert!(!yearly.is_due(FRIDAY_NOON + 1).unwrap());
+
+        let ran = Scheduler::tick(&path, FRIDAY_NOON, |schedule| {
+            match schedule.name.as_str() {
+                "pump" => Ok(true),
+                _ => Err(Error::new(ErrorKind::TimedOut, "no reply")),
+            }
+        })
+        .unwrap();
+        assert_eq!(ran, vec!["pump", "lights"]);
+        // same minute, and the pump isn't due for another minute.
+        let ran = Scheduler::tick(&path, FRIDAY_NOON + 30_000, |_| Ok(true)).unwrap();
+        assert!(ran.is_empty());
+
+        let schedules = Schedules::list(&path).unwrap();
+        assert_eq!(schedules[0].last_status, Some(true));
+        assert_eq!(schedules[1].last_status, Some(false));
+        assert_eq!(schedules[1].last_error.as_deref(), Some("no reply"));
+        assert_eq!(schedules[1].failures, 1);
+
+        let mut paused = schedules[0].clone();
+        paused.enabled = false;
+        Schedules::update(&path, paused).unwrap();
+        let ran = Scheduler::tick(&path, FRIDAY_NOON + 120_000, |_| Ok(true)).unwrap();
+        assert!(ran.is_empty());
+        assert_eq!(Schedules::list(&path).unwrap()[0].runs, 1);
+
+        assert!(Schedules::remove(&path, "lights").unwrap());
+        assert!(!Schedules::remove(&path, "lights").unwrap());
+
+        // a bad expression in the file skips that schedule, not the whole tick.
+        let broken = schedule(
+            "broken",
+            json!({ "kind": "cron", "expression": "61 * * * *" }),
+        );
+        let valve = schedule("valve", json!({ "kind": "interval", "everyMillis": 1000 }));
+        fs::write(&path, json!({ "schedules": [broken, valve] }).to_string()).unwrap();
+        let ran = Scheduler::tick(&path, FRIDAY_NOON, |_| Ok(true)).unwrap();
+        assert_eq!(ran, vec!["valve"]);
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.lock", path)).unwrap();
+    }
+}
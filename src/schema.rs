@@ -0,0 +1,375 @@
+pub mod message_schema {
+
+    use crate::axonmessage::axonmessage::{AxonMessage, Envelope};
+    use crate::command::command::Command;
+    use crate::handshake::handshake::{HandshakeRequest, HandshakeResponse};
+    use crate::query::query::{Query, QueryResponse};
+    use crate::record::record::Record;
+    use crate::state::device_state::State;
+    use crate::timesync::time_sync::{TimeSync, TimeSyncResponse};
+    use schemars::gen::SchemaSettings;
+    use schemars::schema::{InstanceType, Schema, SchemaObject};
+    use schemars::JsonSchema;
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io::{Error, ErrorKind};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::OnceLock;
+
+    static SCHEMAS: OnceLock<BTreeMap<String, Value>> = OnceLock::new();
+    static STRICT: AtomicBool = AtomicBool::new(false);
+
+    // for enums that travel as their discriminant through `serde_repr`, which
+    // schemars can't see.
+    pub fn integer_enum<I: IntoIterator<Item = i64>>(values: I) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            enum_values: Some(values.into_iter().map(Value::from).collect()),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    #[derive(Debug)]
+    struct Failure {
+        path: String,
+        message: String,
+        // the value had the wrong JSON type altogether, which says the least
+        // about what went wrong when picking between alternatives.
+        wrong_type: bool,
+    }
+
+    impl Failure {
+        fn new(path: &str, message: String) -> Failure {
+            Failure {
+                path: path.to_string(),
+                message,
+                wrong_type: false,
+            }
+        }
+    }
+
+    pub struct Schemas;
+
+    impl Schemas {
+        fn generate<T: AxonMessage + JsonSchema>() -> (String, Value) {
+            let root = SchemaSettings::draft07()
+                .into_generator()
+                .into_root_schema_for::<T>();
+            let mut schema = serde_json::to_value(root).expect("schemas serialize to JSON");
+            close_objects(&mut schema);
+            (T::message_type().to_string(), schema)
+        }
+
+        // one schema per built in message, keyed by the `type` it travels under.
+        pub fn all() -> &'static BTreeMap<String, Value> {
+            SCHEMAS.get_or_init(|| {
+                vec![
+                    Self::generate::<HandshakeRequest>(),
+                    Self::generate::<HandshakeResponse>(),
+                    Self::generate::<Record>(),
+                    Self::generate::<State>(),
+                    Self::generate::<Command>(),
+                    Self::generate::<Query>(),
+                    Self::generate::<QueryResponse>(),
+                    Self::generate::<TimeSync>(),
+                    Self::generate::<TimeSyncResponse>(),
+                ]
+                .into_iter()
+                .collect()
+            })
+        }
+
+        pub fn schema(message_type: &str) -> Option<&'static Value> {
+            Self::all().get(message_type)
+        }
+
+        // writes `<type>.schema.json` for every message into `dir`.
+        pub fn write(dir: &str) -> Result<Vec<String>, Error> {
+            fs::create_dir_all(dir)?;
+            let mut written = Vec::new();
+            for (message_type, schema) in Self::all() {
+                let path = Path::new(dir).join(format!("{}.schema.json", message_type));
+                fs::write(&path, serde_json::to_string_pretty(schema)?)?;
+                written.push(path.to_string_lossy().into_owned());
+            }
+            Ok(written)
+        }
+
+        pub fn set_strict(strict: bool) {
+            STRICT.store(strict, Ordering::SeqCst);
+        }
+
+        pub fn is_strict() -> bool {
+            STRICT.load(Ordering::SeqCst)
+        }
+
+        // a no-op unless strict mode is on.
+        pub fn check(envelope: &Envelope) -> Result<(), Error> {
+            if Self::is_strict() {
+                Self::validate(envelope)
+            } else {
+                Ok(())
+            }
+        }
+
+        // custom message types have no schema and always pass.
+        pub fn validate(envelope: &Envelope) -> Result<(), Error> {
+            let schema = match Self::schema(&envelope.message_type) {
+                Some(schema) => schema,
+                None => return Ok(()),
+            };
+            validate(schema, schema, &envelope.payload, "").map_err(|failure| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "invalid `{}` payload at {}: {}",
+                        envelope.message_type,
+                        if failure.path.is_empty() {
+                            "/"
+                        } else {
+                            &failure.path
+                        },
+                        failure.message
+                    ),
+                )
+            })
+        }
+    }
+
+    // serde ignores unknown fields, the published contract doesn't.
+    fn close_objects(schema: &mut Value) {
+        match schema {
+            Value::Object(object) => {
+                if object.contains_key("properties") && !object.contains_key("additionalProperties")
+                {
+                    object.insert(String::from("additionalProperties"), Value::Bool(false));
+                }
+                object.values_mut().for_each(close_objects);
+            }
+            Value::Array(items) => items.iter_mut().for_each(close_objects),
+            _ => (),
+        }
+    }
+
+    fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+        match schema.get("$ref").and_then(Value::as_str) {
+            Some(reference) => reference
+                .strip_prefix("#/definitions/")
+                .and_then(|name| root.get("definitions").and_then(|defs| defs.get(name)))
+                .map(|schema| resolve(root, schema))
+                .unwrap_or(schema),
+            None => schema,
+        }
+    }
+
+    fn has_type(value: &Value, name: &str) -> bool {
+        match name {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "string" => value.is_string(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            _ => false,
+        }
+    }
+
+    fn describe(value: &Value) -> &'static str {
+        match value {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+            Value::Object(_) => "an object",
+        }
+    }
+
+    fn format_range(format: &str) -> Option<(f64, f64)> {
+        match format {
+            "int8" => Some((f64::from(i8::MIN), f64::from(i8::MAX))),
+            "uint8" => Some((0.0, f64::from(u8::MAX))),
+            "int16" => Some((f64::from(i16::MIN), f64::from(i16::MAX))),
+            "uint16" => Some((0.0, f64::from(u16::MAX))),
+            "int32" => Some((f64::from(i32::MIN), f64::from(i32::MAX))),
+            "uint32" => Some((0.0, f64::from(u32::MAX))),
+            "int64" => Some((i64::MIN as f64, i64::MAX as f64)),
+            "uint64" | "uint" => Some((0.0, u64::MAX as f64)),
+            _ => None,
+        }
+    }
+
+    // covers the subset of draft 7 that schemars generates for our messages.
+    fn validate(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), Failure> {
+        let schema = resolve(root, schema);
+
+        if let Some(types) = schema.get("type") {
+            let allowed: Vec<&str> = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                types => types.as_str().into_iter().collect(),
+            };
+            if !allowed.iter().any(|name| has_type(value, name)) {
+                return Err(Failure {
+                    wrong_type: true,
+                    ..Failure::new(
+                        path,
+                        format!("expected {}, got {}", allowed.join(" or "), describe(value)),
+                    )
+                });
+            }
+        }
+
+        if let Some(Value::Array(options)) = schema.get("enum") {
+            if !options.contains(value) {
+                return Err(Failure::new(
+                    path,
+                    format!("{} is not one of {}", value, Value::from(options.clone())),
+                ));
+            }
+        }
+
+        if let Some(number) = value.as_f64() {
+            let range = schema
+                .get("format")
+                .and_then(Value::as_str)
+                .and_then(format_range);
+            let minimum = schema.get("minimum").and_then(Value::as_f64);
+            let maximum = schema.get("maximum").and_then(Value::as_f64);
+            let below = minimum
+                .into_iter()
+                .chain(range.map(|r| r.0))
+                .any(|min| number < min);
+            let above = maximum
+                .into_iter()
+                .chain(range.map(|r| r.1))
+                .any(|max| number > max);
+            if below || above {
+                return Err(Failure::new(path, format!("{} is out of range", value)));
+            }
+        }
+
+        if let Value::Object(object) = value {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        return Err(Failure::new(
+                            path,
+                            format!("missing required field `{}`", name),
+                        ));
+                    }
+                }
+            }
+            let properties = schema.get("properties");
+            let closed = schema.get("additionalProperties") == Some(&Value::Bool(false));
+            for (name, field) in object {
+                let field_path = format!("{}/{}", path, name);
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(field_schema) => validate(root, field_schema, field, &field_path)?,
+                    None if closed => {
+                        return Err(Failure::new(&field_path, String::from("unknown field")))
+                    }
+                    None => (),
+                }
+            }
+        }
+
+        if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+            for (i, item) in items.iter().enumerate() {
+                validate(root, item_schema, item, &format!("{}/{}", path, i))?;
+            }
+        }
+
+        if let Some(Value::Array(options)) = schema.get("allOf") {
+            for option in options {
+                validate(root, option, value, path)?;
+            }
+        }
+
+        for keyword in &["anyOf", "oneOf"] {
+            let options = match schema.get(*keyword) {
+                Some(Value::Array(options)) => options,
+                _ => continue,
+            };
+            let mut failures: Vec<Failure> = options
+                .iter()
+                .filter_map(|option| validate(root, option, value, path).err())
+                .collect();
+            let matched = options.len() - failures.len();
+            if matched == 0 {
+                // report the alternative that got furthest before failing.
+                failures.sort_by_key(|failure| (failure.path.len(), !failure.wrong_type));
+                let mut failure = failures.pop().unwrap_or_else(|| {
+                    Failure::new(path, String::from("matches no allowed shape"))
+                });
+                failure.wrong_type = failure.wrong_type && failure.path == path;
+                return Err(failure);
+            }
+            if *keyword == "oneOf" && matched > 1 {
+                return Err(Failure::new(
+                    path,
+                    String::from("matches more than one allowed shape"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axonmessage::axonmessage::Envelope;
+    use crate::record::fixture::record_json;
+    use crate::schema::message_schema::Schemas;
+    use serde_json::json;
+
+    #[test]
+    fn validate_payloads() {
+        let schemas = Schemas::all();
+        assert_eq!(schemas.len(), 9);
+        assert_eq!(
+            schemas["record"]["definitions"]["RecordType"]["enum"],
+            json!([83, 78])
+        );
+        assert_eq!(schemas["state"]["additionalProperties"], json!(false));
+
+        let record = record_json("device", "temperature", "21.5");
+        assert!(Schemas::validate(&Envelope::new("record", record.clone())).is_ok());
+
+        let mut wrong_type = record.clone();
+        wrong_type["recordType"] = json!(12);
+        let error = Schemas::validate(&Envelope::new("record", wrong_type)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid `record` payload at /recordType: 12 is not one of [83,78]"
+        );
+
+        let mut missing = record;
+        missing.as_object_mut().unwrap().remove("sensorName");
+        missing["unit"] = json!("C");
+        let error = Schemas::validate(&Envelope::new("record", missing)).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("missing required field `sensorName`"));
+
+        let accept = json!({ "handshakeType": 18497, "capabilities": { "versions": [3, 300] } });
+        let error = Schemas::validate(&Envelope::new("handshakeResponse", accept)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid `handshakeResponse` payload at /capabilities/versions/1: 300 is out of range"
+        );
+
+        let response = json!({ "correlationId": 4, "result": { "type": "text", "value": 3 } });
+        let error = Schemas::validate(&Envelope::new("queryResponse", response)).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("at /result/value: expected string"));
+
+        assert!(Schemas::validate(&Envelope::new("inventory", json!(1))).is_ok());
+        assert!(Schemas::check(&Envelope::new("state", json!({}))).is_ok());
+    }
+}
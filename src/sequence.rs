@@ -0,0 +1,212 @@
+pub mod sequence {
+
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    // how far behind the highest sequence number a late frame may still arrive.
+    pub const WINDOW_SIZE: u32 = 64;
+    // sequence numbers wrap, anything less than half the range ahead is newer.
+    const HALF_RANGE: u32 = 1 << 31;
+
+    static SESSIONS: Mutex<BTreeMap<String, Session>> = Mutex::new(BTreeMap::new());
+
+    #[derive(Debug, PartialEq)]
+    pub enum Delivery {
+        Fresh,
+        Duplicate,
+        Gap(u32),
+    }
+
+    #[derive(Default)]
+    pub struct ReplayWindow {
+        highest: Option<u32>,
+        // bit n is set when `highest - n` has been seen.
+        seen: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SequenceStats {
+        pub sent: u64,
+        pub received: u64,
+        pub duplicates: u64,
+        pub gaps: u64,
+        pub missing: u64,
+    }
+
+    #[derive(Default)]
+    struct Session {
+        next_outbound: u32,
+        inbound: ReplayWindow,
+        stats: SequenceStats,
+    }
+
+    impl ReplayWindow {
+        // a frame further back than the window, or a 0 that was already seen,
+        // means the device started counting again, e.g. after a reboot, so the
+        // window starts over from it.
+        pub fn check(&mut self, seq: u32) -> Delivery {
+            let highest = match self.highest {
+                Some(highest) => highest,
+                None => return self.restart(seq),
+            };
+            let distance = seq.wrapping_sub(highest);
+            if distance != 0 && distance < HALF_RANGE {
+                self.seen = if distance >= WINDOW_SIZE {
+                    1
+                } else {
+                    (self.seen << distance) | 1
+                };
+                self.highest = Some(seq);
+                if distance > 1 {
+                    Delivery::Gap(distance - 1)
+                } else {
+                    Delivery::Fresh
+                }
+            } else {
+                let offset = highest.wrapping_sub(seq);
+                let seen = offset < WINDOW_SIZE && self.seen & (1 << offset) != 0;
+                if offset >= WINDOW_SIZE || (seq == 0 && seen) {
+                    debug!(
+                        target: "axon::frame",
+                        "sequence went back from {} to {}, starting over",
+                        highest,
+                        seq
+                    );
+                    self.restart(seq)
+                } else if seen {
+                    Delivery::Duplicate
+                } else {
+                    self.seen |= 1 << offset;
+                    Delivery::Fresh
+                }
+            }
+        }
+
+        fn restart(&mut self, seq: u32) -> Delivery {
+            self.highest = Some(seq);
+            self.seen = 1;
+            Delivery::Fresh
+        }
+    }
+
+    pub struct SequenceTracker;
+
+    impl SequenceTracker {
+        // the device restarts its counter whenever the port is opened, so each
+        // open starts a new session.
+        pub fn reset(port_name: &str) {
+            SESSIONS
+                .lock()
+                .unwrap()
+                .insert(port_name.to_string(), Session::default());
+        }
+
+        // a new handshake is a new exchange, whatever the device counted before
+        // it doesn't make its next frames duplicates.
+        pub fn reset_inbound(port_name: &str) {
+            let mut sessions = SESSIONS.lock().unwrap();
+            sessions.entry(port_name.to_string()).or_default().inbound = ReplayWindow::default();
+        }
+
+        pub fn next_outbound(port_name: &str) -> u32 {
+            let mut sessions = SESSIONS.lock().unwrap();
+            let session = sessions.entry(port_name.to_string()).or_default();
+            let seq = session.next_outbound;
+            session.next_outbound = session.next_outbound.wrapping_add(1);
+            session.stats.sent += 1;
+            seq
+        }
+
+        // frames without a `seq` in their metadata are always delivered.
+        pub fn accept_inbound(port_name: &str, seq: Option<u32>) -> Delivery {
+            let seq = match seq {
+                Some(seq) => seq,
+                None => return Delivery::Fresh,
+            };
+            let mut sessions = SESSIONS.lock().unwrap();
+            let session = sessions.entry(port_name.to_string()).or_default();
+            let delivery = session.inbound.check(seq);
+            match delivery {
+                Delivery::Duplicate => session.stats.duplicates += 1,
+                Delivery::Gap(missing) => {
+                    session.stats.received += 1;
+                    session.stats.gaps += 1;
+                    session.stats.missing += u64::from(missing);
+                }
+                Delivery::Fresh => session.stats.received += 1,
+            }
+            delivery
+        }
+
+        pub fn stats(port_name: &str) -> SequenceStats {
+            SESSIONS
+                .lock()
+                .unwrap()
+                .get(port_name)
+                .map(|session| session.stats.clone())
+                .unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequence::sequence::{Delivery, ReplayWindow, SequenceTracker};
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(window.check(0), Delivery::Fresh);
+        assert_eq!(window.check(1), Delivery::Fresh);
+        assert_eq!(window.check(1), Delivery::Duplicate);
+        assert_eq!(window.check(4), Delivery::Gap(2));
+        assert_eq!(window.check(3), Delivery::Fresh);
+        assert_eq!(window.check(3), Delivery::Duplicate);
+        assert_eq!(window.check(100), Delivery::Gap(95));
+        assert_eq!(window.check(99), Delivery::Fresh);
+        assert_eq!(window.check(99), Delivery::Duplicate);
+        // far behind the window starts over.
+        assert_eq!(window.check(4), Delivery::Fresh);
+        assert_eq!(window.check(5), Delivery::Fresh);
+        assert_eq!(window.check(5), Delivery::Duplicate);
+
+        // a device that reboots sends 0 again.
+        let mut rebooted = ReplayWindow::default();
+        assert_eq!(rebooted.check(0), Delivery::Fresh);
+        assert_eq!(rebooted.check(1), Delivery::Fresh);
+        assert_eq!(rebooted.check(2), Delivery::Fresh);
+        assert_eq!(rebooted.check(0), Delivery::Fresh);
+        assert_eq!(rebooted.check(1), Delivery::Fresh);
+        assert_eq!(rebooted.check(1), Delivery::Duplicate);
+
+        let mut wrapping = ReplayWindow::default();
+        assert_eq!(wrapping.check(u32::MAX - 1), Delivery::Fresh);
+        assert_eq!(wrapping.check(u32::MAX), Delivery::Fresh);
+        assert_eq!(wrapping.check(1), Delivery::Gap(1));
+        assert_eq!(wrapping.check(u32::MAX), Delivery::Duplicate);
+        // a late 0 after the wrap is just late, not a reboot.
+        assert_eq!(wrapping.check(0), Delivery::Fresh);
+        assert_eq!(wrapping.check(1), Delivery::Duplicate);
+    }
+
+    #[test]
+    fn tracker_stats() {
+        let port = "/dev/test-sequence";
+        SequenceTracker::reset(port);
+        assert_eq!(SequenceTracker::next_outbound(port), 0);
+        assert_eq!(SequenceTracker::next_outbound(port), 1);
+        SequenceTracker::accept_inbound(port, Some(0));
+        SequenceTracker::accept_inbound(port, Some(1));
+        SequenceTracker::accept_inbound(port, Some(1));
+        SequenceTracker::accept_inbound(port, Some(4));
+        SequenceTracker::accept_inbound(port, None);
+        let stats = SequenceTracker::stats(port);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.gaps, 1);
+        assert_eq!(stats.missing, 2);
+    }
+}
@@ -1,16 +1,37 @@
 pub mod serial_handler {
 
+    use crate::capture::capture::Capture;
+    use crate::codec::codec::BINARY_MARKER;
+    use crate::sequence::sequence::SequenceTracker;
     use serialport::prelude::*;
+    use std::collections::BTreeMap;
     use std::io::Result as SingleResult;
-    use std::io::{BufRead, BufReader, BufWriter, Write};
+    use std::io::{BufWriter, Error, ErrorKind, Write};
     use std::path::Path;
+    use std::sync::{Mutex, MutexGuard};
+    use std::time::Duration;
+
+    pub const DEFAULT_SETTINGS: SerialPortSettings = SerialPortSettings {
+        baud_rate: 9600,
+        data_bits: DataBits::Eight,
+        flow_control: FlowControl::None,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        timeout: Duration::from_millis(20000),
+    };
+
+    const READ_CHUNK: usize = 256;
+
+    // bytes read past the last frame, by port name
+    static PENDING: Mutex<BTreeMap<String, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
     pub struct SerialData;
 
     impl SerialData {
-       pub fn read_port(mut port: &mut Box<dyn SerialPort>) -> SingleResult<String> {
-            let mut buffer = String::new();
-            let mut reader = BufReader::new(&mut port);
-            reader.read_line(&mut buffer)?;
+       pub fn read_port(port: &mut Box<dyn SerialPort>) -> SingleResult<String> {
+            // goes through the frame buffer so nothing read ahead is lost
+            let buffer = String::from_utf8(Self::read_frame_bytes(port)?)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
             if buffer.contains("\r\n") {
                 let buffer = buffer.replace("\r\n", "");
                 Ok(buffer)
@@ -28,12 +49,105 @@ pub mod serial_handler {
             Ok(true)
         }
 
+        pub fn write_bytes(data: &[u8], port: &mut Box<dyn SerialPort>) -> SingleResult<bool> {
+            port.flush()?;
+            port.write_all(data)?;
+            Ok(true)
+        }
+
+        // reads one frame in chunks: a binary frame by its length, anything else
+        // up to and including the newline. bytes read past the frame are kept per
+        // port for the next call.
+        pub fn read_frame_bytes(port: &mut Box<dyn SerialPort>) -> SingleResult<Vec<u8>> {
+            let name = port.name().unwrap_or_default();
+            let mut buffer = Self::pending().remove(&name).unwrap_or_default();
+            let mut chunk = [0u8; READ_CHUNK];
+            loop {
+                if let Some(length) = Self::frame_length(&buffer) {
+                    let rest = buffer.split_off(length);
+                    if !rest.is_empty() {
+                        Self::pending().insert(name, rest);
+                    }
+                    return Ok(buffer);
+                }
+                match port.read(&mut chunk) {
+                    Ok(0) => return Ok(buffer),
+                    Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        // keep the partial frame so a retry picks up where this stopped
+                        if !buffer.is_empty() {
+                            Self::pending().insert(name, buffer);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        pub fn clear_pending(port_name: &str) {
+            Self::pending().remove(port_name);
+        }
+
+        fn pending() -> MutexGuard<'static, BTreeMap<String, Vec<u8>>> {
+            PENDING.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        fn frame_length(buffer: &[u8]) -> Option<usize> {
+            if buffer.first() == Some(&BINARY_MARKER) {
+                if buffer.len() < 4 {
+                    return None;
+                }
+                let length = 4 + usize::from(u16::from_be_bytes([buffer[2], buffer[3]]));
+                return Some(length).filter(|&length| buffer.len() >= length);
+            }
+            let end = buffer.iter().position(|&byte| byte == b'\n')?;
+            Some(end + 1)
+        }
+
        pub fn open_port(
             settings: SerialPortSettings,
             path: &String,
         ) -> SingleResult<Box<dyn SerialPort>> {
-            let path = Path::new(&path);
-            Ok(serialport::open_with_settings(path, &settings)?)
+            let port = serialport::open_with_settings(Path::new(&path), &settings)?;
+            // keyed like every other sequence lookup, by the port's own name.
+            let name = port.name().unwrap_or_default();
+            SequenceTracker::reset(&name);
+            Self::clear_pending(&name);
+            Ok(Capture::wrap(port))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::capture::{CaptureEntry, Direction, ReplayPort};
+    use crate::serial::serial_handler::SerialData;
+    use serialport::prelude::*;
+
+    #[test]
+    fn read_frames_keeps_remainder() {
+        // two text frames and a binary one arriving in a single read
+        let data = [
+            b"a{}\r\n".to_vec(),
+            b"b{}\n".to_vec(),
+            vec![0x02, 1, 0, 2, 0xa0, 0xa1],
+        ]
+        .concat();
+        let entries = [CaptureEntry {
+            at: 0,
+            port: String::from("test"),
+            direction: Direction::In,
+            data: hex::encode(&data),
+        }];
+        let name = String::from("replay:read_frames_keeps_remainder");
+        SerialData::clear_pending(&name);
+        let mut port: Box<dyn SerialPort> = Box::new(ReplayPort::new(name, &entries).unwrap());
+        assert_eq!(SerialData::read_port(&mut port).unwrap(), "a{}");
+        assert_eq!(SerialData::read_frame_bytes(&mut port).unwrap(), b"b{}\n");
+        assert_eq!(
+            SerialData::read_frame_bytes(&mut port).unwrap(),
+            vec![0x02, 1, 0, 2, 0xa0, 0xa1]
+        );
+    }
+}
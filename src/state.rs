@@ -1,7 +1,9 @@
 pub mod device_state {
-    use crate::axonmessage::axonmessage::{AxonMessage, Message};
-    use crate::handshake::handshake::{AxonMessageType, Handshake};
+    use crate::axonmessage::axonmessage::{AxonMessage, Envelope, Frame, Message, Sendable};
+    use crate::handshake::handshake::{AxonMessageStatus, AxonMessageType, Handshake};
     use crate::serial::serial_handler::SerialData;
+    use hex;
+    use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
     use serialport::prelude::*;
     use std::borrow::BorrowMut;
@@ -10,9 +12,15 @@ pub mod device_state {
     use std::fs::OpenOptions;
     use std::io::prelude::*;
     use std::io::Result as SingleResult;
+    use std::io::{Error, ErrorKind};
     use std::path::Path;
 
-    #[derive(Serialize, Deserialize, Debug)]
+    pub const STATE_PREFIX_BYTE: char = 'S';
+    const MAX_READBACK_LINES: usize = 32;
+    // owner keys and generation hashes are 32 bytes, hex encoded.
+    const HASH_LENGTH: usize = 64;
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
     #[serde(rename_all = "camelCase")]
     pub struct State {
         pub owner_public_key: String,
@@ -20,7 +28,13 @@ pub mod device_state {
         pub gen_hash: String,
     }
 
-    impl AxonMessage for State {}
+    impl AxonMessage for State {
+        fn message_type() -> &'static str {
+            "state"
+        }
+    }
+
+    impl Sendable for State {}
 
     impl State {
         pub fn exists() -> bool {
@@ -69,12 +83,17 @@ pub mod device_state {
             settings: SerialPortSettings,
         ) -> SingleResult<bool> {
             let mut port = SerialData::open_port(settings, &path)?;
-            match Handshake::recieve::<State>(port.borrow_mut(), AxonMessageType::StateMessage) {
+            Self::watch_state_port(state_path, port.borrow_mut())
+        }
+
+        pub fn watch_state_port(
+            state_path: &String,
+            port: &mut Box<dyn SerialPort>,
+        ) -> SingleResult<bool> {
+            match Handshake::recieve::<State>(port, AxonMessageType::StateMessage) {
                 Ok(response) => match response {
-                    Message::Empty => Ok(false),
-                    _ => {
-                        let state: State = serde_json::from_str(&response.to_json_string()?)?;
-                        println!("we have a state response.. {:?}", state);
+                    Message::AxonMessage(state) => {
+                        debug!(target: "axon::state", "received state {:?}", state);
                         State::save_state(
                             state.owner_public_key,
                             state.node_ip,
@@ -83,15 +102,104 @@ pub mod device_state {
                         )?;
                         Ok(true)
                     }
+                    _ => Ok(false),
                 },
-                Err(_) => Ok(false),
+                // nothing arrived yet, or a frame that wasn't a state. anything
+                // else, e.g. the port going away, is for the caller to handle.
+                Err(ref e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::InvalidData
+                    ) =>
+                {
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            }
+        }
+
+        pub fn validate(&self) -> SingleResult<()> {
+            let is_hash = |value: &str| value.len() == HASH_LENGTH && hex::decode(value).is_ok();
+            if !is_hash(&self.owner_public_key) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("owner public key must be {} hex characters", HASH_LENGTH),
+                ));
+            }
+            if !is_hash(&self.gen_hash) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("generation hash must be {} hex characters", HASH_LENGTH),
+                ));
+            }
+            if self.node_ip.is_empty() {
+                return Err(Error::new(ErrorKind::InvalidInput, "node address is empty"));
+            }
+            Ok(())
+        }
+
+        pub fn provision(
+            state: &State,
+            path: &String,
+            settings: SerialPortSettings,
+        ) -> SingleResult<State> {
+            state.validate()?;
+            let mut port = SerialData::open_port(settings, path)?;
+            Self::provision_port(state, port.borrow_mut())
+        }
+
+        // the device writes the state to EEPROM and then echoes back what it
+        // stored, which has to match what was sent.
+        pub fn provision_port(
+            state: &State,
+            port: &mut Box<dyn SerialPort>,
+        ) -> SingleResult<State> {
+            match Handshake::send::<State>(
+                port,
+                state,
+                STATE_PREFIX_BYTE,
+                AxonMessageType::StateMessage,
+            )? {
+                AxonMessageStatus::Success => (),
+                AxonMessageStatus::Failure => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionRefused,
+                        "device did not accept the state",
+                    ))
+                }
             }
+            for _ in 0..MAX_READBACK_LINES {
+                if let Frame::Envelope(envelope) = Handshake::read_frame(port)? {
+                    if let Some(stored) = Self::match_readback(state, envelope)? {
+                        return Ok(stored);
+                    }
+                }
+            }
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                "device did not read back its stored state",
+            ))
+        }
+
+        pub fn match_readback(sent: &State, envelope: Envelope) -> SingleResult<Option<State>> {
+            if !envelope.is::<State>() {
+                return Ok(None);
+            }
+            let stored = envelope.open::<State>()?;
+            if &stored != sent {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("device stored {:?} instead of {:?}", stored, sent),
+                ));
+            }
+            Ok(Some(stored))
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::axonmessage::axonmessage::Envelope;
     use crate::state::device_state::State;
     use rand::Rng;
     use serde_json;
@@ -171,4 +279,33 @@ mod tests {
             empty_state_as_str
         );
     }
+
+    #[test]
+    fn provision_readback() {
+        let state = State {
+            owner_public_key: String::from(
+                "3485D98EFD7EB07ADAFCFD1A157D89DE2796A95E780813C0258AF3F5F84ED8CB",
+            ),
+            node_ip: String::from("http://198.199.80.167:3000"),
+            gen_hash: String::from(
+                "B626827FBD912D95931E03E9718BFE8FFD7D316E9FBB5416ED2B3C072EA32406",
+            ),
+        };
+        assert!(state.validate().is_ok());
+        let truncated = State {
+            gen_hash: String::from("B626827F"),
+            ..state.clone()
+        };
+        assert!(truncated.validate().is_err());
+
+        let echoed = Envelope::wrap(&state, None).unwrap();
+        assert_eq!(
+            State::match_readback(&state, echoed).unwrap(),
+            Some(state.clone())
+        );
+        let corrupted = Envelope::wrap(&truncated, None).unwrap();
+        assert!(State::match_readback(&state, corrupted).is_err());
+        let other = Envelope::new("queryResponse", serde_json::Value::Null);
+        assert_eq!(State::match_readback(&state, other).unwrap(), None);
+    }
 }
@@ -0,0 +1,377 @@
+pub mod device_supervisor {
+
+    use crate::capability::capability::Negotiation;
+    use crate::handshake::handshake::Handshake;
+    use crate::serial::serial_handler::SerialData;
+    use serialport::prelude::*;
+    use serialport::SerialPortType;
+    use std::collections::BTreeMap;
+    use std::io::ErrorKind;
+    use std::io::Result as SingleResult;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const STREAM_BACKOFF: Duration = Duration::from_millis(5);
+
+    // every port a supervisor has open, by path, so one-off senders such as the
+    // scheduler go through the link that owns the port instead of reopening it.
+    static OPEN_PORTS: Mutex<BTreeMap<String, Arc<SharedPort>>> = Mutex::new(BTreeMap::new());
+
+    #[derive(Debug)]
+    pub enum DeviceEvent {
+        Connected { path: String },
+        Disconnected { path: String },
+        Rejected { path: String, reason: String },
+        Record(String),
+        State(bool),
+        ClockSync(String),
+    }
+
+    // the port sits behind its own lock, taken only for I/O. the link lock
+    // guards the connection state and is never held while talking to the
+    // device, so status calls and unplug detection don't wait on a read.
+    pub struct SharedPort {
+        port: Mutex<Box<dyn SerialPort>>,
+        // one-off users waiting for the port, streams step aside for them.
+        waiting: AtomicUsize,
+    }
+
+    struct Link {
+        path: Option<String>,
+        port: Option<Arc<SharedPort>>,
+        // a path that failed the pairing challenge, left alone until it goes away.
+        rejected: Option<String>,
+    }
+
+    type SharedLink = Arc<(Mutex<Link>, Condvar)>;
+
+    pub struct DeviceSupervisor {
+        serial_number: String,
+        poll_interval: Duration,
+        link: SharedLink,
+        running: Arc<AtomicBool>,
+        sender: Sender<DeviceEvent>,
+        // stop flags of the periodic tasks, by name.
+        periodic: Mutex<BTreeMap<String, Arc<AtomicBool>>>,
+        pub events: Arc<Mutex<Receiver<DeviceEvent>>>,
+    }
+
+    // a thread that panicked mid-read leaves nothing half updated behind these
+    // locks, so carry on with the data rather than take every caller down.
+    fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    impl SharedPort {
+        fn new(port: Box<dyn SerialPort>) -> SharedPort {
+            SharedPort {
+                port: Mutex::new(port),
+                waiting: AtomicUsize::new(0),
+            }
+        }
+
+        // goes ahead of any stream, which gives the port up after its current read.
+        pub fn with<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&mut Box<dyn SerialPort>) -> R,
+        {
+            self.waiting.fetch_add(1, Ordering::SeqCst);
+            let mut port = lock(&self.port);
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            f(&mut port)
+        }
+
+        fn stream<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&mut Box<dyn SerialPort>) -> R,
+        {
+            while self.waiting.load(Ordering::SeqCst) > 0 {
+                thread::sleep(STREAM_BACKOFF);
+            }
+            f(&mut lock(&self.port))
+        }
+    }
+
+    impl DeviceSupervisor {
+        pub fn find_port(serial_number: &str) -> SingleResult<Option<String>> {
+            let ports = serialport::available_ports()?;
+            Ok(ports.into_iter().find_map(|info| match info.port_type {
+                SerialPortType::UsbPort(usb)
+                    if usb.serial_number.as_deref() == Some(serial_number) =>
+                {
+                    Some(info.port_name)
+                }
+                _ => None,
+            }))
+        }
+
+        pub fn spawn(
+            serial_number: String,
+            settings: SerialPortSettings,
+            poll_interval: Duration,
+        ) -> DeviceSupervisor {
+            let (sender, receiver) = channel();
+            let supervisor = DeviceSupervisor {
+                serial_number,
+                poll_interval,
+                link: Arc::new((
+                    Mutex::new(Link {
+                        path: None,
+                        port: None,
+                        rejected: None,
+                    }),
+                    Condvar::new(),
+                )),
+                running: Arc::new(AtomicBool::new(true)),
+                sender,
+                periodic: Mutex::new(BTreeMap::new()),
+                events: Arc::new(Mutex::new(receiver)),
+            };
+
+            let serial_number = supervisor.serial_number.clone();
+            let link = supervisor.link.clone();
+            let running = supervisor.running.clone();
+            let sender = supervisor.sender.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::SeqCst) {
+                    let found = Self::find_port(&serial_number).unwrap_or(None);
+                    Self::reconcile(&link, found, settings, &sender);
+                    thread::sleep(poll_interval);
+                }
+                Self::disconnect(&link, &sender);
+            });
+            supervisor
+        }
+
+        // opens the port and redoes the capability handshake when the device shows
+        // up, and drops the port when it goes away. a device that re-enumerates
+        // under a new path is dropped first and picked up again on the next poll.
+        fn reconcile(
+            link: &SharedLink,
+            found: Option<String>,
+            settings: SerialPortSettings,
+            sender: &Sender<DeviceEvent>,
+        ) {
+            let current = {
+                let mut guard = lock(&link.0);
+                if guard.rejected.is_some() && guard.rejected != found {
+                    guard.rejected = None;
+                }
+                if guard.rejected.is_some() {
+                    return;
+                }
+                guard.path.clone()
+            };
+            match (current, found) {
+                (None, Some(path)) => {
+                    if let Ok(mut port) = SerialData::open_port(settings, &path) {
+                        // nothing is sent until the capability exchange succeeds. a
+                        // device that fails the pairing challenge is left alone until
+                        // it goes away, anything else is tried again on the next poll.
+                        if let Err(e) = Handshake::negotiate(&mut port) {
+                            if e.kind() == ErrorKind::PermissionDenied {
+                                let reason = e.to_string();
+                                lock(&link.0).rejected = Some(path.clone());
+                                let _ = sender.send(DeviceEvent::Rejected { path, reason });
+                            } else {
+                                warn!(
+                                    target: "axon::supervisor",
+                                    "capability exchange on {} failed: {}",
+                                    path,
+                                    e
+                                );
+                            }
+                            return;
+                        }
+                        let (state, cvar) = &**link;
+                        let mut guard = lock(state);
+                        let port = Arc::new(SharedPort::new(port));
+                        lock(&OPEN_PORTS).insert(path.clone(), port.clone());
+                        guard.path = Some(path.clone());
+                        guard.port = Some(port);
+                        cvar.notify_all();
+                        let _ = sender.send(DeviceEvent::Connected { path });
+                    }
+                }
+                (Some(current), Some(path)) if current != path => Self::disconnect(link, sender),
+                (Some(_), None) => Self::disconnect(link, sender),
+                _ => (),
+            }
+        }
+
+        fn disconnect(link: &SharedLink, sender: &Sender<DeviceEvent>) {
+            let mut guard = lock(&link.0);
+            guard.port = None;
+            if let Some(path) = guard.path.take() {
+                lock(&OPEN_PORTS).remove(&path);
+                let _ = sender.send(DeviceEvent::Disconnected { path });
+            }
+        }
+
+        pub fn shared_port(path: &str) -> Option<Arc<SharedPort>> {
+            lock(&OPEN_PORTS).get(path).cloned()
+        }
+
+        pub fn serial_number(&self) -> &str {
+            &self.serial_number
+        }
+
+        pub fn negotiation(&self) -> Option<Negotiation> {
+            let path = lock(&self.link.0).path.clone()?;
+            Negotiation::load(&path)
+        }
+
+        pub fn is_connected(&self) -> bool {
+            lock(&self.link.0).port.is_some()
+        }
+
+        pub fn is_running(&self) -> bool {
+            self.running.load(Ordering::SeqCst)
+        }
+
+        // waits for the device to be connected and hands out its port. the link
+        // lock is released before anyone talks to the device.
+        fn wait_for_port(
+            link: &SharedLink,
+            running: &AtomicBool,
+            poll_interval: Duration,
+        ) -> Option<Arc<SharedPort>> {
+            let (state, cvar) = &**link;
+            let mut guard = lock(state);
+            while guard.port.is_none() && running.load(Ordering::SeqCst) {
+                guard = cvar
+                    .wait_timeout(guard, poll_interval)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+            guard.port.clone()
+        }
+
+        // blocks until the device is connected, then runs `f` against the open port.
+        pub fn with_port<F, R>(&self, f: F) -> SingleResult<R>
+        where
+            F: FnOnce(&mut Box<dyn SerialPort>) -> SingleResult<R>,
+        {
+            match Self::wait_for_port(&self.link, &self.running, self.poll_interval) {
+                Some(port) => port.with(f),
+                None => Err(std::io::Error::from(std::io::ErrorKind::NotConnected)),
+            }
+        }
+
+        // runs `stream` for every frame while the device is attached, and picks it
+        // back up on the fresh port after a reconnect. each frame goes through its
+        // own `Handshake`, so nothing from the old connection is carried over.
+        pub fn spawn_stream<F>(&self, mut stream: F)
+        where
+            F: FnMut(&mut Box<dyn SerialPort>) -> SingleResult<Option<DeviceEvent>>
+                + Send
+                + 'static,
+        {
+            let link = self.link.clone();
+            let running = self.running.clone();
+            let sender = self.sender.clone();
+            let poll_interval = self.poll_interval;
+            thread::spawn(move || {
+                while running.load(Ordering::SeqCst) {
+                    let result = match Self::wait_for_port(&link, &running, poll_interval) {
+                        Some(port) => port.stream(&mut stream),
+                        None => Ok(None),
+                    };
+                    match result {
+                        Ok(Some(event)) => {
+                            if sender.send(event).is_err() {
+                                break;
+                            }
+                        }
+                        Ok(None) => (),
+                        // read timeouts and unplugged ports end up here, give the
+                        // supervisor a chance to notice before trying again.
+                        Err(_) => thread::sleep(poll_interval),
+                    }
+                }
+            });
+        }
+
+        // runs `task` once every `interval` while the device is attached. unlike
+        // `spawn_stream` it only takes the port when it is due, and it goes ahead
+        // of any stream waiting on the port. spawning a task under a name that is
+        // already running replaces the old one.
+        pub fn spawn_periodic<F>(&self, name: &str, interval: Duration, mut task: F)
+        where
+            F: FnMut(&mut Box<dyn SerialPort>) -> SingleResult<Option<DeviceEvent>>
+                + Send
+                + 'static,
+        {
+            let stop = Arc::new(AtomicBool::new(false));
+            if let Some(old) = lock(&self.periodic).insert(name.to_string(), stop.clone()) {
+                old.store(true, Ordering::SeqCst);
+            }
+            let name = name.to_string();
+            let link = self.link.clone();
+            let running = self.running.clone();
+            let sender = self.sender.clone();
+            let poll_interval = self.poll_interval;
+            thread::spawn(move || {
+                let mut due = Instant::now();
+                while running.load(Ordering::SeqCst) && !stop.load(Ordering::SeqCst) {
+                    let now = Instant::now();
+                    if now < due {
+                        thread::sleep(poll_interval.min(due - now));
+                        continue;
+                    }
+                    let port = lock(&link.0).port.clone();
+                    let result = match port {
+                        Some(port) => port.with(&mut task),
+                        None => {
+                            thread::sleep(poll_interval);
+                            continue;
+                        }
+                    };
+                    due = Instant::now() + interval;
+                    match result {
+                        Ok(Some(event)) => {
+                            if sender.send(event).is_err() {
+                                break;
+                            }
+                        }
+                        Ok(None) => (),
+                        // a missed run waits for the next one, the device may be
+                        // busy or halfway through a reconnect.
+                        Err(e) => warn!(
+                            target: "axon::supervisor",
+                            "{} failed, retrying in {:?}: {}",
+                            name,
+                            interval,
+                            e
+                        ),
+                    }
+                }
+            });
+        }
+
+        // true when a task was running under `name`.
+        pub fn stop_periodic(&self, name: &str) -> bool {
+            match lock(&self.periodic).remove(name) {
+                Some(stop) => {
+                    stop.store(true, Ordering::SeqCst);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        pub fn shutdown(&self) {
+            self.running.store(false, Ordering::SeqCst);
+            self.link.1.notify_all();
+        }
+    }
+
+    impl Drop for DeviceSupervisor {
+        fn drop(&mut self) {
+            self.shutdown();
+        }
+    }
+}
@@ -0,0 +1,191 @@
+pub mod time_sync {
+
+    use crate::axonmessage::axonmessage::{AxonMessage, Envelope, Frame, Sendable};
+    use crate::handshake::handshake::{AxonMessageType, Handshake};
+    use crate::serial::serial_handler::SerialData;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serialport::prelude::*;
+    use std::borrow::BorrowMut;
+    use std::collections::BTreeMap;
+    use std::io::{Error, ErrorKind};
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub const TIME_SYNC_PREFIX_BYTE: char = 'T';
+    const MAX_RESPONSE_LINES: usize = 32;
+
+    static CLOCKS: Mutex<BTreeMap<String, ClockSync>> = Mutex::new(BTreeMap::new());
+
+    // the host's wall clock, in milliseconds since the epoch.
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TimeSync {
+        pub host_time: u64,
+    }
+
+    // `device_time` is the device's own clock when the sync arrived, usually
+    // `millis()`. `host_time` is echoed back to pair it with the request.
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TimeSyncResponse {
+        pub host_time: u64,
+        pub device_time: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ClockSync {
+        // add to a device timestamp to get wall clock milliseconds.
+        pub offset_millis: i64,
+        pub round_trip_millis: u64,
+        pub device_time: u64,
+        pub synced_at: u64,
+        // how fast the device clock runs against the host's since the previous
+        // sync, in parts per million. positive when the device runs slow.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub drift_ppm: Option<f64>,
+    }
+
+    impl AxonMessage for TimeSync {
+        fn message_type() -> &'static str {
+            "timeSync"
+        }
+    }
+
+    impl Sendable for TimeSync {}
+
+    impl AxonMessage for TimeSyncResponse {
+        fn message_type() -> &'static str {
+            "timeSyncResponse"
+        }
+    }
+
+    impl ClockSync {
+        // assumes the response took as long to come back as the request took to
+        // arrive.
+        pub fn measure(
+            sent_at: u64,
+            received_at: u64,
+            device_time: u64,
+            previous: Option<&ClockSync>,
+        ) -> ClockSync {
+            let round_trip_millis = received_at.saturating_sub(sent_at);
+            let midpoint = sent_at + round_trip_millis / 2;
+            let offset_millis = midpoint as i64 - device_time as i64;
+            // a device clock that went backwards has rebooted, its old offset
+            // says nothing about drift.
+            let drift_ppm = previous
+                .filter(|previous| device_time > previous.device_time)
+                .filter(|previous| received_at > previous.synced_at)
+                .map(|previous| {
+                    let elapsed = (received_at - previous.synced_at) as f64;
+                    (offset_millis - previous.offset_millis) as f64 * 1_000_000.0 / elapsed
+                });
+            ClockSync {
+                offset_millis,
+                round_trip_millis,
+                device_time,
+                synced_at: received_at,
+                drift_ppm,
+            }
+        }
+
+        pub fn to_wall_clock(&self, device_time: u64) -> u64 {
+            (device_time as i64 + self.offset_millis).max(0) as u64
+        }
+    }
+
+    impl TimeSync {
+        pub fn now() -> u64 {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default()
+        }
+
+        pub fn sync(path: String, settings: SerialPortSettings) -> Result<ClockSync, Error> {
+            let mut port = SerialData::open_port(settings, &path)?;
+            Self::sync_port(port.borrow_mut())
+        }
+
+        // the handshake runs first so its latency doesn't end up in the round trip.
+        pub fn sync_port(port: &mut Box<dyn SerialPort>) -> Result<ClockSync, Error> {
+            let port_name = port.name().unwrap_or_default();
+            Handshake::open_session(port, AxonMessageType::TimeSyncMessage)?;
+            let sent_at = Self::now();
+            let request = TimeSync { host_time: sent_at };
+            Handshake::write_frame(port, TIME_SYNC_PREFIX_BYTE, &request)?;
+            for _ in 0..MAX_RESPONSE_LINES {
+                if let Frame::Envelope(envelope) = Handshake::read_frame(port)? {
+                    if let Some(device_time) = Self::match_response(envelope, sent_at)? {
+                        let received_at = Self::now();
+                        let mut clocks = CLOCKS.lock().unwrap();
+                        let clock = ClockSync::measure(
+                            sent_at,
+                            received_at,
+                            device_time,
+                            clocks.get(&port_name),
+                        );
+                        debug!(
+                            target: "axon::time",
+                            "offset {}ms round trip {}ms on {}",
+                            clock.offset_millis,
+                            clock.round_trip_millis,
+                            port_name
+                        );
+                        clocks.insert(port_name, clock.clone());
+                        return Ok(clock);
+                    }
+                }
+            }
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("no time sync response on {}", port_name),
+            ))
+        }
+
+        pub fn match_response(envelope: Envelope, host_time: u64) -> Result<Option<u64>, Error> {
+            if !envelope.is::<TimeSyncResponse>() {
+                return Ok(None);
+            }
+            let response = envelope.open::<TimeSyncResponse>()?;
+            if response.host_time == host_time {
+                Ok(Some(response.device_time))
+            } else {
+                Ok(None)
+            }
+        }
+
+        pub fn clock(port_name: &str) -> Option<ClockSync> {
+            CLOCKS.lock().unwrap().get(port_name).cloned()
+        }
+
+        // `None` until the port has been synced at least once.
+        pub fn to_wall_clock(port_name: &str, device_time: u64) -> Option<u64> {
+            Self::clock(port_name).map(|clock| clock.to_wall_clock(device_time))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timesync::time_sync::ClockSync;
+
+    #[test]
+    fn offset_and_drift() {
+        let first = ClockSync::measure(1_000_000, 1_000_040, 5_000, None);
+        assert_eq!(first.round_trip_millis, 40);
+        assert_eq!(first.offset_millis, 995_020);
+        assert_eq!(first.drift_ppm, None);
+        assert_eq!(first.to_wall_clock(6_000), 1_001_020);
+
+        // ten minutes later the device clock has fallen 60ms behind.
+        let second = ClockSync::measure(1_600_000, 1_600_040, 604_940, Some(&first));
+        assert_eq!(second.offset_millis, 995_080);
+        assert_eq!(second.drift_ppm, Some(100.0));
+
+        let rebooted = ClockSync::measure(1_700_000, 1_700_040, 100, Some(&second));
+        assert_eq!(rebooted.drift_ppm, None);
+    }
+}